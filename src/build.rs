@@ -1,12 +1,15 @@
 use std::collections::{HashMap, HashSet};
 use std::hash::{DefaultHasher, Hasher};
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Command, ExitStatus};
+use std::sync::atomic::{AtomicI32, Ordering};
 
 use serde::Deserialize;
 
 use crate::prelude::*;
-use crate::fuzzy::*;
+use crate::resolve;
+use crate::failed::Failure;
+use crate::settings::{buildah, settings};
 use crate::podman::*;
 use crate::CommandExt;
 
@@ -38,6 +41,15 @@ pub struct Metadata {
     /// Whether or not to allow instantiating a container from this definition.
     #[serde(default = "yes")]
     pub instantiate: bool,
+    /// The group this definition belongs to, if any.
+    #[serde(default)]
+    pub group: Option<String>,
+    /// The pod containers created from this definition should join, if any.
+    #[serde(default)]
+    pub pod: Option<String>,
+    /// A short, human-readable description of the definition.
+    #[serde(default)]
+    pub description: Option<String>,
 }
 
 fn yes() -> bool { true }
@@ -99,34 +111,52 @@ impl Definition {
         }
     }
 
-    /// Given a name, attempt to find and fetch the corresponding definition.
+    /// Given a name, a unique prefix of a name, or a glob matching exactly one name,
+    /// attempt to find and fetch the corresponding definition.
     pub fn find(name: &str) -> Result<Self> {
+        let names = Self::names()?;
+        let name  = resolve::one("definition", name, &names)?;
+
+        Self::from_path(
+            definition_directory()?.join(format!("{name}.box"))
+        )
+        .context("Failed to load and parse definition")
+    }
+
+    /// Given a name, a unique prefix of a name, or a glob, attempt to find and fetch
+    /// every corresponding definition.
+    pub fn matching(name: &str) -> Result<Definitions> {
+        let names = Self::names()?;
+        let dir   = definition_directory()?;
+
+        resolve::all("definition", name, &names)?
+            .into_iter()
+            .map(|name| {
+                Self::from_path(dir.join(format!("{name}.box")))
+                    .context("Failed to load and parse definition")
+            })
+            .collect()
+    }
+
+    /// The names of all definitions, without loading them.
+    pub fn names() -> Result<Vec<String>> {
         use std::fs;
         use std::ffi::OsStr;
 
-        let dir  = definition_directory()?;
-        let stem = OsStr::new(name);
-
-        let entry = fs::read_dir(dir)
+        let names = fs::read_dir(definition_directory()?)
             .context("Fault when starting definition search")?
             .filter_map(Result::ok)
-            .find(|e| e.path().file_stem() == stem.into());
-
-        if let Some(entry) = entry {
-            Self::from_path(entry.path())
-                .context("Failed to load and parse definition")
-        }
-        else {
-            let suggestion = match Self::alternative(name) {
-                Some(m) => format!("Did you mean '{}'?", m),
-                None => "Did you make a typo?".to_string(),
-            };
-
-            let err = eyre!("Tried to operate on a definition ({name}) that does not exist")
-                .suggestion(suggestion);
+            .map(|e| e.path())
+            .filter(|p| p.extension() == OsStr::new("box").into())
+            .filter_map(|p| {
+                p
+                    .file_stem()
+                    .and_then(OsStr::to_str)
+                    .map(str::to_owned)
+            })
+            .collect();
 
-            Err(err)
-        }
+        Ok(names)
     }
 
     // Given a name, determines whether or not a matching definition exists.
@@ -229,7 +259,10 @@ impl Definition {
     }
 
     /// Build the definition.
-    pub fn build(&self) -> Result<()> {
+    /// 
+    /// If `no_cache` is true, build checkpoints are not restored from (though new ones are still made.)
+    /// If `keep_failed` is true, the working container is kept if a step fails.
+    pub fn build(&self, no_cache: bool, keep_failed: bool) -> Result<()> {
         use std::fs;
         use colored::Colorize;
 
@@ -268,8 +301,26 @@ impl Definition {
             )
         }
 
+        // Working containers are named up front and recorded as they are created,
+        // so they can always be cleaned up - however the build ends.
+        let ctr = self.working_container();
+
+        let containers = crate::state_directory()?
+            .join(format!("{ctr}.working"));
+
+        fs::write(&containers, "")
+            .context("Fault when creating record of working containers")?;
+
         let extend_env = |mut cmd: Command| {
             cmd
+                .env(
+                    "__BOX_BUILD_WORKING",
+                    &ctr
+                )
+                .env(
+                    "__BOX_BUILD_CONTAINERS",
+                    &containers
+                )
                 .env(
                     "__BOX_BUILD_PATH",
                     &self.path
@@ -297,12 +348,36 @@ impl Definition {
                 .env(
                     "__BOX_BUILD_INSTANTIATE",
                     self.meta.instantiate.to_string()
+                )
+                .env(
+                    "__BOX_BUILD_DEPENDS",
+                    self.depends_on().join("\x1F")
+                )
+                .env(
+                    "__BOX_BUILD_GROUP",
+                    self.meta.group.as_deref().unwrap_or_default()
+                )
+                .env(
+                    "__BOX_BUILD_POD",
+                    self.meta.pod.as_deref().unwrap_or_default()
+                )
+                .env(
+                    "__BOX_BUILDAH",
+                    &settings().buildah
+                )
+                .env(
+                    "__BOX_BUILD_NO_CACHE",
+                    if no_cache { "1" } else { "" }
+                )
+                .env(
+                    "__BOX_BUILD_KEEP_FAILED",
+                    if keep_failed { "1" } else { "" }
                 );
 
             cmd
         };
 
-        if self.bang.contains("fish") {
+        let (cmd, kind) = if self.bang.contains("fish") {
             let mut cmd = Command::new("fish");
 
             cmd
@@ -310,9 +385,7 @@ impl Definition {
                 .arg("bx init fish | source")
                 .arg(&self.path);
 
-            extend_env(cmd)
-                .spawn_ok()
-                .context("Fault when evaluating Fish-based definition")?;
+            (cmd, "Fish")
         }
         else {
             let script = format!(
@@ -339,49 +412,199 @@ impl Definition {
                 .arg("-c")
                 .arg(script);
 
-            extend_env(cmd)
-                .spawn_ok()
-                .context("Fault when evaluating POSIX-based definition")?;
+            (cmd, "POSIX")
+        };
+
+        let outcome = run_harness(extend_env(cmd));
+
+        self.clean_up(&containers, keep_failed);
+
+        match outcome.with_context(|| format!("Fault when evaluating {kind}-based definition"))? {
+            Outcome::Success => Ok(()),
+            Outcome::Failed(status) => {
+                let err = eyre!("Definition script returned non-zero exit code")
+                    .section(status.to_string().header("Status:"))
+                    .wrap_err(format!("Fault when evaluating {kind}-based definition"));
+
+                Err(err)
+            },
+            Outcome::Cancelled(signal) => {
+                eprintln!(
+                    "{} {} {}",
+                    "Cancelled".bold().yellow(),
+                    "build of".bold().bright_white(),
+                    self.name().bold().green()
+                );
+
+                Err(Cancelled(signal).into())
+            }
         }
-        
-        Ok(())
     }
 
-    /// Finds an alternative definition name that is similar to the given name.
-    ///
-    /// This function uses fuzzy matching to find a definition name that is close to the given name.
-    /// If no match is found, it returns `None`.
-    pub fn alternative(name: &str) -> Option<String> {
-        use std::ffi::OsStr;
+    /// Remove every working container recorded by the build harness, except one kept after a failure.
+    fn clean_up(&self, record: &Path, keep_failed: bool) {
+        let containers = std::fs::read_to_string(record)
+            .unwrap_or_default();
+
+        let _ = std::fs::remove_file(record);
+
+        let kept = Failure::load(self.name())
+            .ok()
+            .filter(|_| keep_failed)
+            .map(|f| f.container);
+
+        for ctr in containers.lines().filter(|c| kept.as_deref() != Some(c)) {
+            // The harness records each container before creating it, so it may never have existed.
+            let exists = buildah()
+                .args(["inspect", "--type", "container", ctr])
+                .output()
+                .is_ok_and(|o| o.status.success());
 
-        let defs = match Self::enumerate() {
-            Ok(defs) => defs,
-            Err(err) => {
-                warn!("Failed to enumerate definitions for fuzzy matching: {}", err);
-                return None;
+            if !exists {
+                continue;
             }
-        };
 
-        let names: Vec<_> = defs
-            .iter()
-            .filter_map(|d| {
-                d
-                    .path
-                    .file_stem()
-                    .and_then(OsStr::to_str)
+            if let Err(err) = buildah().args(["rm", ctr]).output_ok() {
+                warn!("Could not remove working container {ctr}: {err}");
+            }
+        }
+    }
+
+    /// The name given to the working container while the definition is being built.
+    fn working_container(&self) -> String {
+        let name: String = self
+            .name()
+            .chars()
+            .map(|c| match c.is_ascii_alphanumeric() || "_.-".contains(c) {
+                true  => c,
+                false => '-'
             })
             .collect();
 
-        let mut fuzzy = Fuzzy::new();
+        format!("box-working-{name}-{}", std::process::id())
+    }
+}
 
-        for s in names { fuzzy.add(s); }
+/// The error returned when a build is cancelled by the provided signal.
+#[derive(Debug)]
+pub struct Cancelled(pub i32);
 
-        fuzzy
-            .find(name)
-            .first()
-            .map(|(_, s)|
-                s.to_string()
-            )
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Build cancelled by signal {}", self.0)
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// How running a definition's build harness ended.
+enum Outcome {
+    Success,
+    Failed(ExitStatus),
+    /// Interrupted by the provided signal.
+    Cancelled(i32),
+}
+
+/// The PID of the running build harness, if any.
+static HARNESS: AtomicI32 = AtomicI32::new(0);
+
+/// The last signal received while the build harness was running, if any.
+static SIGNAL: AtomicI32 = AtomicI32::new(0);
+
+extern "C" fn forward_signal(signal: libc::c_int) {
+    SIGNAL.store(signal, Ordering::SeqCst);
+
+    let pid = HARNESS.load(Ordering::SeqCst);
+
+    if pid > 0 {
+        // The harness runs in its own process group, so this reaches everything it has started
+        // (such as a running `buildah run`) rather than just the shell.
+        //
+        // SAFETY: kill is async-signal-safe.
+        unsafe { libc::kill(-pid, signal); }
+    }
+}
+
+/// Run a definition's build harness to completion, forwarding SIGINT and SIGTERM to it and everything
+/// it starts (rather than letting them kill Box before it can clean up.)
+fn run_harness(mut cmd: Command) -> Result<Outcome> {
+    use std::io::IsTerminal;
+    use std::os::unix::process::{CommandExt, ExitStatusExt};
+
+    debug!("Shelling out; command is {cmd:?}");
+
+    cmd.process_group(0);
+
+    // A background process group that reads from the terminal is stopped, which would hang any step
+    // that prompts - so if Box has the terminal, the harness is given it for as long as it runs.
+    //
+    // SAFETY: tcgetpgrp and getpgrp have no preconditions.
+    let foreground = std::io::stdin().is_terminal()
+        && unsafe { libc::tcgetpgrp(libc::STDIN_FILENO) == libc::getpgrp() };
+
+    if foreground {
+        // SAFETY: only async-signal-safe functions are called between fork and exec.
+        unsafe {
+            cmd.pre_exec(|| {
+                take_terminal();
+                Ok(())
+            });
+        }
+    }
+
+    let handler = forward_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+
+    // SAFETY: the handler only touches atomics and calls kill, both of which are async-signal-safe.
+    let previous = unsafe {
+        [
+            libc::signal(libc::SIGINT, handler),
+            libc::signal(libc::SIGTERM, handler)
+        ]
+    };
+
+    let status = cmd
+        .spawn()
+        .and_then(|mut child| {
+            HARNESS.store(child.id() as i32, Ordering::SeqCst);
+            child.wait()
+        });
+
+    HARNESS.store(0, Ordering::SeqCst);
+
+    if foreground {
+        take_terminal();
+    }
+
+    // SAFETY: these are the handlers that were installed before.
+    unsafe {
+        libc::signal(libc::SIGINT, previous[0]);
+        libc::signal(libc::SIGTERM, previous[1]);
+    }
+
+    let status = status.context("Fault when running build harness")?;
+
+    let outcome = match SIGNAL.swap(0, Ordering::SeqCst) {
+        0 if status.success() => Outcome::Success,
+        // While the harness has the terminal, Ctrl-C reaches it directly rather than through Box.
+        0 => match status.signal() {
+            Some(signal @ (libc::SIGINT | libc::SIGTERM)) => Outcome::Cancelled(signal),
+            _ => Outcome::Failed(status)
+        },
+        signal => Outcome::Cancelled(signal)
+    };
+
+    Ok(outcome)
+}
+
+/// Make the calling process's group the terminal's foreground process group.
+fn take_terminal() {
+    // SAFETY: these are async-signal-safe. SIGTTOU is ignored for the duration, as a background
+    // process group would otherwise be stopped for trying to take the terminal.
+    unsafe {
+        let previous = libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+
+        libc::tcsetpgrp(libc::STDIN_FILENO, libc::getpgrp());
+        libc::signal(libc::SIGTTOU, previous);
     }
 }
 
@@ -408,7 +631,7 @@ impl Definition {
 
         if let Some(data) = Editor::new()
             .require_save(true)
-            .edit("#!/bin/bash\n\n")
+            .edit(&settings().template)
             .context("Fault when editing new definition")?
         {
             std::fs::write(&path, data)
@@ -505,6 +728,9 @@ impl Definition {
         std::fs::remove_file(path)
             .context("Fault when removing definition")?;
 
+        crate::export::remove(&name)?;
+        crate::checkpoint::prune(&name, &[])?;
+
         Ok(())
     }
 }
@@ -513,6 +739,7 @@ impl Definition {
 /// 
 ///  Existence checks these options, in this order:
 /// - `$BOX_DEFINITION_DIR`
+/// - The `definitions` setting
 /// - `$XDG_CONFIG_HOME/box`
 /// - `$HOME/.config/box`
 pub fn definition_directory() -> Result<PathBuf> {
@@ -522,23 +749,16 @@ pub fn definition_directory() -> Result<PathBuf> {
                 PathBuf::from(dir)
             );
         }
-    
-        if let Ok(xdg_config) = std::env::var("XDG_CONFIG_HOME") {
-            return Some(
-                PathBuf::from(xdg_config)
-                    .join("box")
-            );
-        }
-    
-        if let Ok(home) = std::env::var("HOME") {
+
+        if let Some(dir) = &settings().definitions {
             return Some(
-                PathBuf::from(home)
-                    .join(".config")
-                    .join("box")
+                dir.to_owned()
             );
         }
 
-        None
+        config_home()
+            .ok()
+            .map(|dir| dir.join("box"))
     };
 
     match options() {
@@ -553,18 +773,45 @@ pub fn definition_directory() -> Result<PathBuf> {
         None => {
             let err = eyre!("Could not find a valid directory for definitions")
                 .note("Box needs a place to store container definitions.")
-                .suggestion("You likely have something wrong with your environment; Box tries:\n\t* $BOX_DEFINITION_DIR\n\t* $XDG_CONFIG_HOME/box\n\t* $HOME/.config/box\n... in that order.");
+                .suggestion("You likely have something wrong with your environment; Box tries:\n\t* $BOX_DEFINITION_DIR\n\t* the definitions setting\n\t* $XDG_CONFIG_HOME/box\n\t* $HOME/.config/box\n... in that order.");
 
             Err(err)
         }
     }
 }
 
+/// Determines the user's configuration directory.
+///
+/// - `$XDG_CONFIG_HOME`
+/// - `$HOME/.config`
+pub fn config_home() -> Result<PathBuf> {
+    xdg_home("XDG_CONFIG_HOME", ".config")
+}
+
+/// Determines one of the user's XDG base directories, given its environment variable
+/// and its location relative to `$HOME`.
+pub fn xdg_home(var: &str, fallback: &str) -> Result<PathBuf> {
+    if let Ok(dir) = std::env::var(var) {
+        Ok(PathBuf::from(dir))
+    }
+    else if let Ok(home) = std::env::var("HOME") {
+        Ok(PathBuf::from(home).join(fallback))
+    }
+    else {
+        let err = eyre!("Neither ${var} nor $HOME is set")
+            .suggestion(format!("Make sure ${var} or $HOME is set."));
+
+        Err(err)
+    }
+}
+
 /// Given a slice of definition names, attempt to fetch and build them.
 /// 
 /// - Alternately, if `all` is true, this function will enumerate all definitions and attempt to build them.
 /// - By default, Box skips building a definition if both it and its dependencies are unchanged; `force` overrides this behavior.
-pub fn build_set(defs: &[String], all: bool, force: bool) -> Result<()> {   
+/// - `no_cache` makes every step run again, rather than being restored from a build checkpoint.
+/// - `keep_failed` keeps the working container of a failed step around for `bx debug`.
+pub fn build_set(defs: &[String], all: bool, force: bool, no_cache: bool, keep_failed: bool) -> Result<()> {   
     use colored::Colorize;
     
     use petgraph::Graph;
@@ -576,7 +823,7 @@ pub fn build_set(defs: &[String], all: bool, force: bool) -> Result<()> {
             let (defs, errors): (Vec<_>, Vec<_>) = defs
                 .iter()
                 .map(String::as_ref)
-                .map(Definition::find)
+                .map(Definition::matching)
                 .partition(Result::is_ok);
             
             if !errors.is_empty() {
@@ -593,9 +840,13 @@ pub fn build_set(defs: &[String], all: bool, force: bool) -> Result<()> {
                 return Err(err)
             }
             else {
+                // Globs may overlap with each other and with explicitly named definitions.
+                let mut seen = HashSet::new();
+
                 defs
                     .into_iter()
-                    .map(Result::unwrap)
+                    .flat_map(Result::unwrap)
+                    .filter(|d| seen.insert(d.name().to_owned()))
                     .collect()
             }
         },
@@ -717,7 +968,7 @@ pub fn build_set(defs: &[String], all: bool, force: bool) -> Result<()> {
         
     if force {
         for idx in topo {
-            graph[idx].build()?;
+            graph[idx].build(no_cache, keep_failed)?;
         }
 
         debug!("Finished building definition set!");
@@ -757,7 +1008,7 @@ pub fn build_set(defs: &[String], all: bool, force: bool) -> Result<()> {
 
         // If no image with a corresponding path exists, build.
         let Some(hashes) = path_hash.get(&def.path) else {
-            def.build()?;
+            def.build(no_cache, keep_failed)?;
             continue
         };
 
@@ -766,7 +1017,7 @@ pub fn build_set(defs: &[String], all: bool, force: bool) -> Result<()> {
         let (own, tree) = hashes;
         
         if *own != def.hash || *tree != def.tree {
-            def.build()?;
+            def.build(no_cache, keep_failed)?;
             continue
         }
 
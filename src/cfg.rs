@@ -0,0 +1,526 @@
+//! The vocabulary of runtime options that can be baked into an image with `CFG`.
+use std::net::IpAddr;
+
+use crate::prelude::*;
+
+/// Describes a runtime option that can be set with `CFG`.
+///
+/// Values are stored in the image as `\x1F`-separated `box.<name>` annotations.
+#[derive(Debug, Clone, Copy)]
+pub struct Annotation {
+    /// The name of the option, as used with `CFG`.
+    pub name     : &'static str,
+    /// The `podman run` flag the option corresponds to, or `None` if its values
+    /// are passed to `podman run` verbatim.
+    pub flag     : Option<&'static str>,
+    /// Whether or not the option can have more than one value.
+    ///
+    /// Setting a non-repeatable option again replaces its old value.
+    pub repeat   : bool,
+    /// A short description of the option.
+    pub help     : &'static str,
+    /// Checks that a single value for the option is well-formed.
+    pub validate : fn(&str) -> Result<()>,
+}
+
+impl Annotation {
+    /// Look up an option by name.
+    pub fn find(name: &str) -> Option<&'static Self> {
+        ANNOTATIONS
+            .iter()
+            .find(|a| a.name == name)
+    }
+
+    /// The annotation key the option's values are stored under.
+    pub fn key(&self) -> String {
+        format!("box.{}", self.name)
+    }
+}
+
+/// Creates a command line value parser that validates values for the option with the provided name.
+pub fn parser(name: &'static str) -> impl Fn(&str) -> std::result::Result<String, String> + Clone + Send + Sync {
+    move |value| {
+        let option = Annotation::find(name)
+            .expect("Option should exist");
+
+        (option.validate)(value)
+            .map(|_| value.to_owned())
+            .map_err(|e| e.to_string())
+    }
+}
+
+const fn option(
+    name     : &'static str,
+    repeat   : bool,
+    help     : &'static str,
+    validate : fn(&str) -> Result<()>
+) -> Annotation {
+    Annotation {
+        name,
+        flag: Some(name),
+        repeat,
+        help,
+        validate
+    }
+}
+
+/// List of annotations that can be modified by CFG.
+pub const ANNOTATIONS: &[Annotation] = &[
+    Annotation {
+        name     : "args",
+        flag     : None,
+        repeat   : true,
+        help     : "Additional arbitrary arguments to pass to podman run",
+        validate : any,
+    },
+    option("add-host", true, "Add a custom host-to-IP mapping (host:ip)", add_host),
+    option("cap-add", true, "Add a Linux capability", capability),
+    option("cap-drop", true, "Remove a Linux capability", capability),
+    option("cpus", false, "Number of CPUs the container can utilize", cpus),
+    option("device", true, "Add a host device (host[:container][:permissions])", device),
+    option("dns", true, "Set a custom DNS server", dns),
+    option("env", true, "Set an environment variable (KEY=VALUE, or KEY to copy from the host)", env),
+    option("env-file", true, "Read environment variables from a file on the host", any),
+    option("group-add", true, "Add an additional group to the container's user", group),
+    option("hostname", false, "Set the container hostname (defaults to the name of the box)", name),
+    option("init", false, "Run an init process inside the container (true or false)", boolean),
+    option("label", true, "Set a label on the container (KEY=VALUE)", label),
+    option("memory", false, "Container memory limit (b, k, m or g suffix)", size),
+    option("mount", true, "Add a mount, in --mount syntax", mount),
+    option("network", true, "Attach the container to a network (creating it if needed), or set a network mode", network),
+    option("pids-limit", false, "Limit the number of processes in the container (-1 for unlimited)", pids_limit),
+    option("pod", false, "Join the container to a pod, creating it if needed", name),
+    option("publish", true, "Publish a container port to the host ([[ip:][host]:]container[/protocol])", publish),
+    option("restart", false, "Set the container restart policy", restart),
+    option("secret", true, "Give the container access to a secret", secret),
+    Annotation {
+        name     : "secret-from",
+        flag     : Some("secret"),
+        repeat   : true,
+        help     : "Provision a secret from the host when the container is created (NAME=file:PATH, NAME=env:VARIABLE or NAME=cmd:COMMAND)",
+        validate : secret_from,
+    },
+    option("security-opt", true, "Set a security option", security_opt),
+    option("shm-size", false, "Size of /dev/shm (b, k, m or g suffix)", size),
+    option("systemd", false, "Run the container in systemd mode (true, false or always)", systemd),
+    option("tmpfs", true, "Mount a tmpfs (path[:options])", tmpfs),
+    option("ulimit", true, "Set a ulimit (name=soft[:hard])", ulimit),
+    option("userns", false, "Set the user namespace mode", userns),
+];
+
+/// List of presets that can be applied with `PRESET`, and what they do.
+pub const PRESETS: &[(&str, &str)] = &[
+    ("cp-user", "Copy a host user (defaults to the current user) into the image, with passwordless sudo"),
+    ("ssh-agent", "Share the host's SSH agent socket with the container"),
+    ("devices", "Give the container access to host devices (creates a privileged container!)"),
+    ("bind-fix", "Disable SELinux labeling and keep the host user ID, so bind mounts just work"),
+];
+
+fn any(value: &str) -> Result<()> {
+    if value.is_empty() {
+        bail!("Value is empty")
+    }
+
+    Ok(())
+}
+
+fn boolean(value: &str) -> Result<()> {
+    one_of(value, &["true", "false"])
+}
+
+fn systemd(value: &str) -> Result<()> {
+    one_of(value, &["true", "false", "always"])
+}
+
+fn one_of(value: &str, options: &[&str]) -> Result<()> {
+    if !options.contains(&value) {
+        bail!("Expected one of {}", options.join(", "))
+    }
+
+    Ok(())
+}
+
+fn name(value: &str) -> Result<()> {
+    let valid = value
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphanumeric()) && value
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "_.-".contains(c));
+
+    if !valid {
+        bail!("Names must start with a letter or digit, and may only contain letters, digits, '_', '.' and '-'")
+    }
+
+    Ok(())
+}
+
+/// Check whether a `network` value is one of Podman's network modes (e.g. `host` or `container:NAME`),
+/// rather than the name of a network.
+pub fn is_network_mode(value: &str) -> bool {
+    let mode = value
+        .split_once(':')
+        .map_or(value, |(m, _)| m);
+
+    match value.contains(':') {
+        true  => matches!(mode, "container" | "ns" | "slirp4netns" | "pasta"),
+        false => matches!(mode, "bridge" | "host" | "none" | "private" | "slirp4netns" | "pasta")
+    }
+}
+
+fn network(value: &str) -> Result<()> {
+    if is_network_mode(value) {
+        return Ok(())
+    }
+
+    name(value)
+        .context("Not a valid network name or mode (bridge, host, none, private, slirp4netns[:OPTIONS], pasta[:OPTIONS], container:ID or ns:PATH)")
+}
+
+fn capability(value: &str) -> Result<()> {
+    let cap = value
+        .to_uppercase();
+
+    let cap = cap
+        .trim_start_matches("CAP_");
+
+    if cap != "ALL" && (cap.is_empty() || !cap.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')) {
+        bail!("Not a valid capability name; see man capabilities")
+    }
+
+    Ok(())
+}
+
+fn cpus(value: &str) -> Result<()> {
+    match value.parse::<f64>() {
+        Ok(n) if n > 0.0 => Ok(()),
+        _ => bail!("Expected a positive number")
+    }
+}
+
+fn size(value: &str) -> Result<()> {
+    let digits = value.trim_end_matches(|c: char| "bkmgBKMG".contains(c));
+
+    if value.len() - digits.len() > 1 || digits.parse::<u64>().is_err() {
+        bail!("Expected a number with an optional b, k, m or g suffix")
+    }
+
+    Ok(())
+}
+
+fn pids_limit(value: &str) -> Result<()> {
+    match value.parse::<i64>() {
+        Ok(n) if n >= -1 => Ok(()),
+        _ => bail!("Expected a whole number, or -1 for unlimited")
+    }
+}
+
+fn device(value: &str) -> Result<()> {
+    if !value.starts_with('/') {
+        bail!("Expected an absolute path to a device on the host")
+    }
+
+    if let Some(perms) = value.split(':').nth(2) {
+        if perms.is_empty() || !perms.chars().all(|c| "rwm".contains(c)) {
+            bail!("Device permissions may only contain r, w and m")
+        }
+    }
+
+    Ok(())
+}
+
+fn dns(value: &str) -> Result<()> {
+    if value != "none" && value.parse::<IpAddr>().is_err() {
+        bail!("Expected an IP address, or 'none'")
+    }
+
+    Ok(())
+}
+
+fn add_host(value: &str) -> Result<()> {
+    let Some((host, ip)) = value.split_once(':') else {
+        bail!("Expected host:ip")
+    };
+
+    if host.is_empty() {
+        bail!("Host name is empty")
+    }
+
+    if ip != "host-gateway" && ip.parse::<IpAddr>().is_err() {
+        bail!("Expected an IP address, or 'host-gateway'")
+    }
+
+    Ok(())
+}
+
+fn env(value: &str) -> Result<()> {
+    let key = value
+        .split_once('=')
+        .map_or(value, |(k, _)| k);
+
+    if key.is_empty() || key.contains(char::is_whitespace) {
+        bail!("Expected KEY=VALUE or KEY, with no whitespace in KEY")
+    }
+
+    Ok(())
+}
+
+fn label(value: &str) -> Result<()> {
+    match value.split_once('=') {
+        Some((k, _)) if !k.is_empty() => Ok(()),
+        _ => bail!("Expected KEY=VALUE")
+    }
+}
+
+fn group(value: &str) -> Result<()> {
+    if value == "keep-groups" || value.parse::<u32>().is_ok() {
+        return Ok(())
+    }
+
+    name(value)
+}
+
+fn mount(value: &str) -> Result<()> {
+    let mut kind = None;
+
+    for option in value.split(',') {
+        if let Some(("type", t)) = option.split_once('=') {
+            kind = Some(t);
+        }
+    }
+
+    let Some(kind) = kind else {
+        bail!("Mounts must specify a type (e.g. type=bind)")
+    };
+
+    one_of(kind, &["bind", "volume", "tmpfs", "image", "devpts", "glob", "ramfs", "artifact"])
+        .context("Unknown mount type")
+}
+
+fn tmpfs(value: &str) -> Result<()> {
+    if !value.starts_with('/') {
+        bail!("Expected an absolute path inside the container")
+    }
+
+    Ok(())
+}
+
+fn publish(value: &str) -> Result<()> {
+    let (ports, protocol) = value
+        .split_once('/')
+        .unwrap_or((value, "tcp"));
+
+    one_of(protocol, &["tcp", "udp", "sctp"])
+        .context("Unknown protocol")?;
+
+    let port = |p: &str| {
+        let (lo, hi) = p
+            .split_once('-')
+            .unwrap_or((p, p));
+
+        lo.parse::<u16>().is_ok() && hi.parse::<u16>().is_ok()
+    };
+
+    let mut parts = ports.rsplit(':');
+
+    let container = parts.next().unwrap_or_default();
+    let host      = parts.next();
+
+    if !port(container) || host.is_some_and(|h| !h.is_empty() && !port(h)) {
+        bail!("Expected [[ip:][host]:]container[/protocol], where ports are numbers or ranges")
+    }
+
+    Ok(())
+}
+
+fn restart(value: &str) -> Result<()> {
+    let (policy, retries) = value
+        .split_once(':')
+        .map_or((value, None), |(p, r)| (p, Some(r)));
+
+    one_of(policy, &["no", "on-failure", "always", "unless-stopped"])?;
+
+    if let Some(retries) = retries {
+        if policy != "on-failure" || retries.parse::<u32>().is_err() {
+            bail!("Only on-failure can specify a number of retries (on-failure:N)")
+        }
+    }
+
+    Ok(())
+}
+
+fn secret(value: &str) -> Result<()> {
+    let name = value
+        .split(',')
+        .next()
+        .unwrap_or_default();
+
+    any(name)
+}
+
+fn secret_from(value: &str) -> Result<()> {
+    let usage = "Expected NAME=file:PATH, NAME=env:VARIABLE or NAME=cmd:COMMAND";
+
+    let Some((secret, source)) = value.split_once('=') else {
+        bail!(usage)
+    };
+
+    name(secret)?;
+
+    let Some((kind, spec)) = source.split_once(':') else {
+        bail!(usage)
+    };
+
+    one_of(kind, &["file", "env", "cmd"])
+        .context("Unknown secret source")?;
+
+    any(spec)
+}
+
+fn security_opt(value: &str) -> Result<()> {
+    let key = value
+        .split_once(['=', ':'])
+        .map_or(value, |(k, _)| k);
+
+    one_of(key, &["apparmor", "label", "mask", "no-new-privileges", "seccomp", "proc-opts", "unmask"])
+        .context("Unknown security option")
+}
+
+fn ulimit(value: &str) -> Result<()> {
+    let Some((_, limits)) = value.split_once('=') else {
+        bail!("Expected name=soft[:hard]")
+    };
+
+    for limit in limits.split(':') {
+        if limit != "-1" && limit.parse::<u64>().is_err() {
+            bail!("Limits must be whole numbers, or -1 for unlimited")
+        }
+    }
+
+    Ok(())
+}
+
+fn userns(value: &str) -> Result<()> {
+    let mode = value
+        .split_once(':')
+        .map_or(value, |(m, _)| m);
+
+    one_of(mode, &["", "auto", "host", "keep-id", "nomap", "ns", "container", "private"])
+        .context("Unknown user namespace mode")?;
+
+    if matches!(mode, "ns" | "container") && !value.contains(':') {
+        bail!("The {mode} mode needs a value ({mode}:...)")
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Check that every value in `good` is accepted by an option, and every value in `bad` is rejected.
+    fn check(option: &str, good: &[&str], bad: &[&str]) {
+        let validate = Annotation::find(option)
+            .expect("Option should exist")
+            .validate;
+
+        for value in good {
+            assert!(validate(value).is_ok(), "{option} should accept {value:?}");
+        }
+
+        for value in bad {
+            assert!(validate(value).is_err(), "{option} should reject {value:?}");
+        }
+    }
+
+    #[test]
+    fn general() {
+        check("args", &["--init", "-e=A"], &[""]);
+        check("env-file", &["./vars.env"], &[""]);
+        check("init", &["true", "false"], &["yes", ""]);
+        check("systemd", &["true", "false", "always"], &["sometimes"]);
+        check("hostname", &["web", "web-1.local", "1box"], &["", "-web", "web box", "web/1"]);
+        check("pod", &["stack", "stack_2"], &["", ".stack"]);
+        check("label", &["a=b", "a="], &["a", "=b"]);
+        check("env", &["A=1", "A", "A=b c"], &["", "=1", "A B=1"]);
+        check("group-add", &["wheel", "1000", "keep-groups"], &["", "-wheel"]);
+    }
+
+    #[test]
+    fn resources() {
+        check("cpus", &["1", "0.5", "16"], &["0", "-1", "many", ""]);
+        check("memory", &["512m", "2g", "2G", "1024", "64k", "100b"], &["", "1.5g", "2gb", "g", "10t"]);
+        check("shm-size", &["64m"], &["64 m"]);
+        check("pids-limit", &["-1", "0", "4096"], &["-2", "lots", ""]);
+        check("ulimit", &["nofile=1024:2048", "core=-1", "nproc=10"], &["nofile", "nofile=lots", "nofile=1:x"]);
+    }
+
+    #[test]
+    fn devices_and_mounts() {
+        check("device", &["/dev/dri", "/dev/dri:/dev/dri", "/dev/fuse:/dev/fuse:rwm"], &["dev/dri", "/dev/dri:/dev/dri:x", "/dev/dri:/dev/dri:"]);
+        check(
+            "mount",
+            &["type=bind,src=/a,dst=/b", "type=volume,src=data,dst=/data", "dst=/t,type=tmpfs"],
+            &["src=/a,dst=/b", "type=nfs,src=/a,dst=/b", ""]
+        );
+        check("tmpfs", &["/tmp", "/run:size=64m"], &["tmp", ""]);
+    }
+
+    #[test]
+    fn networking() {
+        check(
+            "network",
+            &["web", "my-net", "host", "none", "bridge", "private", "slirp4netns", "slirp4netns:allow_host_loopback=true", "pasta", "container:db", "ns:/run/netns/x"],
+            &["", "-net", "my net", "bogus:mode"]
+        );
+        check("dns", &["1.1.1.1", "::1", "none"], &["dns.example.com", ""]);
+        check("add-host", &["db:10.0.0.2", "host:host-gateway"], &["db", ":10.0.0.2", "db:nowhere"]);
+        check(
+            "publish",
+            &["80", "8080:80", "127.0.0.1:8080:80", "127.0.0.1::80", "8000-8010:80-90", "53:53/udp", "9:9/sctp"],
+            &["", "http", "80/icmp", "8080:http", "99999"]
+        );
+
+        assert!(is_network_mode("host"));
+        assert!(is_network_mode("container:db"));
+        assert!(!is_network_mode("web"));
+        assert!(!is_network_mode("web:1"));
+    }
+
+    #[test]
+    fn security() {
+        check("cap-add", &["NET_ADMIN", "cap_sys_ptrace", "ALL"], &["", "NET ADMIN", "CAP_"]);
+        check("cap-drop", &["ALL", "MKNOD"], &["-"]);
+        check(
+            "security-opt",
+            &["no-new-privileges", "label=disable", "seccomp=unconfined", "apparmor=unconfined", "mask=/proc/kcore"],
+            &["", "selinux=off"]
+        );
+        check("userns", &["keep-id", "keep-id:uid=1000", "auto", "host", "", "nomap", "container:db"], &["shared", "ns"]);
+    }
+
+    #[test]
+    fn policies() {
+        check("restart", &["no", "always", "unless-stopped", "on-failure", "on-failure:3"], &["", "sometimes", "always:3", "on-failure:x"]);
+    }
+
+    #[test]
+    fn secrets() {
+        check("secret", &["token", "token,type=env,target=TOKEN"], &["", ",type=env"]);
+        check(
+            "secret-from",
+            &["token=file:./token", "token=env:TOKEN", "token=cmd:pass show token"],
+            &["token", "token=./token", "token=vault:x", "token=file:", "-token=env:TOKEN"]
+        );
+    }
+
+    #[test]
+    fn repeatability() {
+        let single = ["cpus", "memory", "userns", "restart", "pod", "hostname", "shm-size", "pids-limit", "systemd", "init"];
+
+        for a in ANNOTATIONS {
+            assert_eq!(a.repeat, !single.contains(&a.name), "unexpected repeatability for {}", a.name);
+        }
+    }
+}
@@ -0,0 +1,472 @@
+//! Checkpointed builds, which make up for the lack of layer caching.
+//!
+//! After each `RUN` and `ADD`, the working container is committed as a checkpoint image, tagged with a key
+//! derived from the key of the previous step, the working container's configuration, the directive itself
+//! and the contents of any host files it reads (the sources of an `ADD`, or the bind mounts of a `RUN`.) When a definition is rebuilt, any step whose key matches an
+//! existing checkpoint is skipped by recreating the working container from that checkpoint instead.
+use std::collections::BTreeMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::prelude::*;
+use crate::podman::*;
+use crate::settings::{buildah, podman};
+use crate::CommandExt;
+
+/// The repository checkpoint images are tagged under; the tag is the key of the step.
+const REPOSITORY: &str = "localhost/box-checkpoint";
+
+/// The annotation that records the keys of every step so far, oldest first.
+const CHAIN: &str = "box.checkpoint";
+
+/// Run a `RUN` or `ADD` step in the working container, restoring it from a checkpoint instead if one exists.
+///
+/// `directive` is everything needed to reproduce the step, and `sources` are any files or directories
+/// on the host whose contents it depends on.
+pub fn step(ctr: &str, directive: &[&str], sources: &[&str], run: impl FnOnce() -> Result<()>) -> Result<()> {
+    let key   = key(ctr, directive, sources)?;
+    let image = format!("{REPOSITORY}:{key}");
+
+    let no_cache = std::env::var("__BOX_BUILD_NO_CACHE")
+        .is_ok_and(|v| !v.is_empty());
+
+    if !no_cache && exists(&image)? {
+        info!("Restoring {ctr} from checkpoint {key}");
+
+        return restore(ctr, &image)
+    }
+
+    run()?;
+
+    push_annotation(ctr, CHAIN, &key)?;
+
+    // The manager annotation is what keeps checkpoints out of `bx images` and friends.
+    write_annotation(ctr, "manager", vec!["box-checkpoint"])?;
+
+    let committed = buildah()
+        .args(["commit", "--quiet", ctr, &image])
+        .output_ok()
+        .context("Fault when committing build checkpoint");
+
+    write_annotation(ctr, "manager", vec!["box"])?;
+
+    committed?;
+
+    Ok(())
+}
+
+/// Remove every checkpoint of the named definition, except those in the provided chain of keys.
+pub fn prune(name: &str, keep: &[&str]) -> Result<()> {
+    let tags = podman()
+        .args([
+            "image",
+            "ls",
+            "--filter",
+            &format!("reference={REPOSITORY}"),
+            "--format",
+            "{{.Tag}}"
+        ])
+        .output_ok()
+        .context("Fault when enumerating build checkpoints")?;
+
+    let images: Vec<_> = tags
+        .lines()
+        .filter(|t| !keep.contains(t))
+        .map(|t| format!("{REPOSITORY}:{t}"))
+        .collect();
+
+    if images.is_empty() {
+        return Ok(())
+    }
+
+    let raw_json = podman()
+        .args(["image", "inspect", "--format", "json"])
+        .args(&images)
+        .output_ok()
+        .context("Failed to inspect build checkpoints")?;
+
+    let inspected: Vec<Image> = serde_json::from_str(&raw_json)
+        .context("Failed to deserialize image JSON")?;
+
+    for (reference, image) in images.iter().zip(inspected) {
+        if image.annotation("box.name") != Some(name) {
+            continue;
+        }
+
+        debug!("Removing stale checkpoint {reference}");
+
+        // Checkpoints still in use by a working container can't be removed, but
+        // that's no reason to fail the build.
+        if let Err(err) = podman().args(["rmi", reference]).output_ok() {
+            warn!("Could not remove stale checkpoint {reference}: {err}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Get the keys of every step the working container has gone through so far, oldest first.
+pub fn chain(ctr: &str) -> Result<Vec<String>> {
+    let chain = inspect(ctr)?
+        .annotations
+        .remove(CHAIN)
+        .unwrap_or_default()
+        .split('\x1F')
+        .filter(|k| !k.is_empty())
+        .map(str::to_owned)
+        .collect();
+
+    Ok(chain)
+}
+
+/// The parts of a working container's state that determine the key of the next step.
+#[derive(Debug, Deserialize)]
+struct WorkingContainer {
+    #[serde(rename = "FromImageID", default)]
+    from        : String,
+    #[serde(rename = "ImageAnnotations", default)]
+    annotations : BTreeMap<String, String>,
+    #[serde(rename = "OCIv1", default)]
+    oci         : serde_json::Value,
+}
+
+fn inspect(ctr: &str) -> Result<WorkingContainer> {
+    let raw_json = buildah()
+        .args(["inspect", "--type", "container", ctr])
+        .output_ok()
+        .context("Failed to inspect working container")?;
+
+    serde_json::from_str(&raw_json)
+        .context("Failed to deserialize working container JSON")
+}
+
+/// Compute the key of the next step.
+fn key(ctr: &str, directive: &[&str], sources: &[&str]) -> Result<String> {
+    hash_step(inspect(ctr)?, directive, sources)
+}
+
+/// Hash the state of a working container together with the next step.
+fn hash_step(state: WorkingContainer, directive: &[&str], sources: &[&str]) -> Result<String> {
+    let WorkingContainer { from, mut annotations, mut oci } = state;
+
+    let mut hasher = DefaultHasher::new();
+
+    // After the first step, the chain (which is part of the annotations) already identifies the
+    // base image - and a container restored from a checkpoint has a different one.
+    if annotations.get(CHAIN).is_none_or(|c| c.is_empty()) {
+        from.hash(&mut hasher);
+    }
+
+    // These change whenever the definition does, so they would invalidate every checkpoint.
+    annotations.retain(|k, _| k.starts_with("box.") && k != "box.hash" && k != "box.tree");
+    annotations.hash(&mut hasher);
+
+    // Buildah stamps this on every image it commits, so it would differ after restoring a checkpoint.
+    if let Some(labels) = oci
+        .pointer_mut("/config/Labels")
+        .and_then(|l| l.as_object_mut())
+    {
+        labels.remove("io.buildah.version");
+    }
+
+    oci
+        .pointer("/config")
+        .map(serde_json::Value::to_string)
+        .hash(&mut hasher);
+
+    directive.hash(&mut hasher);
+
+    for source in sources {
+        hash_path(Path::new(source), &mut hasher)?;
+    }
+
+    Ok(
+        format!("{:016x}", hasher.finish())
+    )
+}
+
+/// Hash the contents of a file or directory (recursively.) Anything that isn't on the host
+/// (e.g. a URL) is left to the directive itself.
+/// 
+/// Symbolic links are hashed by their target, rather than followed.
+fn hash_path(path: &Path, hasher: &mut DefaultHasher) -> Result<()> {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    let Ok(meta) = fs::symlink_metadata(path) else {
+        return Ok(())
+    };
+
+    meta.permissions().mode().hash(hasher);
+
+    if meta.is_symlink() {
+        fs::read_link(path)
+            .context("Fault when reading symbolic link to checkpoint")?
+            .hash(hasher);
+    }
+    else if meta.is_dir() {
+        let mut entries: Vec<_> = fs::read_dir(path)
+            .context("Fault when reading directory to checkpoint")?
+            .map(|e| e.map(|e| e.path()))
+            .collect::<Result<_, _>>()
+            .context("Fault when reading directory to checkpoint")?;
+
+        entries.sort();
+
+        for entry in entries {
+            entry.file_name().hash(hasher);
+            hash_path(&entry, hasher)?;
+        }
+    }
+    else {
+        fs::read(path)
+            .context("Fault when reading file to checkpoint")?
+            .hash(hasher);
+    }
+
+    Ok(())
+}
+
+/// Get the host paths bind-mounted by a set of `buildah run` options (`-v`, `--volume` and `--mount`),
+/// so a `RUN` can depend on their contents.
+pub fn bind_sources(options: &[String]) -> Vec<&str> {
+    let mut out  = vec![];
+    let mut args = options.iter().map(String::as_str);
+
+    // Named volumes don't live on the host (at least, not anywhere Box should be looking.)
+    let is_path = |src: &str| src.starts_with(['/', '.', '~']) || src.contains('/');
+
+    while let Some(arg) = args.next() {
+        let (flag, value) = match arg.split_once('=') {
+            Some((flag @ ("--volume" | "--mount"), value)) => (flag, Some(value)),
+            _ => match arg.strip_prefix("-v") {
+                Some(value) if !value.is_empty() && !arg.starts_with("--") => ("-v", Some(value)),
+                _ => (arg, None)
+            }
+        };
+
+        if !matches!(flag, "-v" | "--volume" | "--mount") {
+            continue;
+        }
+
+        let Some(value) = value.or_else(|| args.next()) else {
+            break
+        };
+
+        if flag == "--mount" {
+            let mut bind   = true;
+            let mut source = None;
+
+            for option in value.split(',') {
+                match option.split_once('=') {
+                    Some(("type", kind)) => bind = kind == "bind",
+                    Some(("src" | "source", src)) => source = Some(src),
+                    _ => ()
+                }
+            }
+
+            out.extend(source.filter(|_| bind));
+        }
+        else if let Some(src) = value.split(':').next().filter(|s| is_path(s)) {
+            out.push(src);
+        }
+    }
+
+    out
+}
+
+fn exists(image: &str) -> Result<bool> {
+    let output = podman()
+        .args(["image", "exists", image])
+        .output()
+        .context("Failed to check if build checkpoint exists")?;
+
+    Ok(
+        output.status.success()
+    )
+}
+
+/// Recreate the working container from a checkpoint, under the same name and with the same `FROM` options.
+fn restore(ctr: &str, image: &str) -> Result<()> {
+    buildah()
+        .args(["rm", ctr])
+        .output_ok()
+        .context("Fault when removing working container to restore checkpoint")?;
+
+    buildah()
+        .arg("from")
+        .args(from_options())
+        .args(["--quiet", "--name", ctr, image])
+        .output_ok()
+        .context("Fault when restoring working container from checkpoint")?;
+
+    // The checkpoint may have been made by an older version of the definition.
+    let env = |key| std::env::var(key).unwrap_or_default();
+
+    buildah()
+        .arg("config")
+        .args(["-a", "manager=box"])
+        .args(["-a", &format!("box.path={}", env("__BOX_BUILD_PATH"))])
+        .args(["-a", &format!("box.hash={}", env("__BOX_BUILD_HASH"))])
+        .args(["-a", &format!("box.tree={}", env("__BOX_BUILD_TREE"))])
+        .arg(ctr)
+        .output_ok()
+        .context("Fault when updating restored working container")?;
+
+    Ok(())
+}
+
+/// The options originally passed to `FROM` (recorded by the build harness), minus the image
+/// and anything that doesn't make sense for a local checkpoint.
+fn from_options() -> Vec<String> {
+    let recorded = std::env::var("__BOX_BUILD_FROM").unwrap_or_default();
+
+    parse_from_options(&recorded)
+}
+
+fn parse_from_options(recorded: &str) -> Vec<String> {
+    let mut args: Vec<_> = recorded
+        .split('\x1F')
+        .filter(|a| !a.is_empty())
+        .collect();
+
+    // The image always comes last.
+    args.pop();
+
+    let mut out = vec![];
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        match arg {
+            "--name" => { args.next(); },
+            a if a.starts_with("--name=") || a.starts_with("--pull") => (),
+            a => out.push(a.to_owned())
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(chain: &str, config: serde_json::Value) -> WorkingContainer {
+        WorkingContainer {
+            from        : "sha256:base".to_owned(),
+            annotations : BTreeMap::from([
+                (CHAIN.to_owned(), chain.to_owned()),
+                ("box.name".to_owned(), "web".to_owned()),
+                ("box.hash".to_owned(), "1234".to_owned()),
+            ]),
+            oci         : serde_json::json!({ "config": config }),
+        }
+    }
+
+    /// A fresh, empty directory for a test to work in.
+    fn scratch(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(
+            format!("box-checkpoint-{name}-{}", std::process::id())
+        );
+
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        dir
+    }
+
+    fn hash(path: &Path) -> u64 {
+        let mut hasher = DefaultHasher::new();
+
+        hash_path(path, &mut hasher).unwrap();
+
+        hasher.finish()
+    }
+
+    #[test]
+    fn keys() {
+        let config = serde_json::json!({ "Env": ["A=1"], "Labels": {} });
+        let key = |state, directive: &[&str]| hash_step(state, directive, &[]).unwrap();
+
+        let base = key(state("", config.clone()), &["run", "--", "make"]);
+
+        // Deterministic, and insensitive to the definition's hash and Buildah's version label.
+        assert_eq!(base, key(state("", config.clone()), &["run", "--", "make"]));
+
+        let mut rehashed = state("", serde_json::json!({ "Env": ["A=1"], "Labels": { "io.buildah.version": "1.2" } }));
+        rehashed.annotations.insert("box.hash".to_owned(), "5678".to_owned());
+
+        assert_eq!(base, key(rehashed, &["run", "--", "make"]));
+
+        // Sensitive to the directive, the configuration and the chain so far.
+        assert_ne!(base, key(state("", config.clone()), &["run", "--", "make", "install"]));
+        assert_ne!(base, key(state("", serde_json::json!({ "Env": ["A=2"], "Labels": {} })), &["run", "--", "make"]));
+        assert_ne!(base, key(state("abc", config.clone()), &["run", "--", "make"]));
+
+        // After the first step, the base image is identified by the chain instead.
+        let mut restored = state("abc", config.clone());
+        restored.from = "sha256:checkpoint".to_owned();
+
+        assert_eq!(key(state("abc", config.clone()), &["run"]), key(restored, &["run"]));
+    }
+
+    #[test]
+    fn paths() {
+        let dir = scratch("paths");
+
+        std::fs::create_dir(dir.join("src")).unwrap();
+        std::fs::write(dir.join("src/main.c"), "int main() {}").unwrap();
+
+        let before = hash(&dir);
+
+        assert_eq!(before, hash(&dir));
+
+        std::fs::write(dir.join("src/main.c"), "int main() { return 1; }").unwrap();
+
+        assert_ne!(before, hash(&dir));
+
+        // Links are hashed by target, so loops don't recurse forever.
+        std::os::unix::fs::symlink("..", dir.join("src/loop")).unwrap();
+
+        let linked = hash(&dir);
+
+        std::fs::remove_file(dir.join("src/loop")).unwrap();
+        std::os::unix::fs::symlink(".", dir.join("src/loop")).unwrap();
+
+        assert_ne!(linked, hash(&dir));
+
+        // Missing paths (e.g. URLs) are left to the directive.
+        hash(&dir.join("https://example.com/file"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn binds() {
+        let options: Vec<_> = [
+            "-v", "./src:/src",
+            "-v/abs:/abs:ro",
+            "--volume=cache:/cache",
+            "--volume", "~/x:/x",
+            "--mount", "type=bind,src=/host,dst=/ctr",
+            "--mount=type=tmpfs,dst=/tmp",
+            "--mount=type=volume,source=named,dst=/named",
+            "--env", "-v=not-a-volume",
+        ]
+        .into_iter()
+        .map(str::to_owned)
+        .collect();
+
+        assert_eq!(bind_sources(&options), ["./src", "/abs", "~/x", "/host"]);
+    }
+
+    #[test]
+    fn from() {
+        let recorded = ["--pull=newer", "-v", "/a:/b", "--name", "mine", "--name=other", "fedora"].join("\x1F");
+
+        assert_eq!(parse_from_options(&recorded), ["-v", "/a:/b"]);
+        assert!(parse_from_options("").is_empty());
+    }
+}
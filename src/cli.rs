@@ -1,4 +1,9 @@
-use clap::{Args, Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use clap_complete::engine::ArgValueCandidates;
+use serde::{Deserialize, Serialize};
+
+use crate::cfg;
+use crate::complete;
 
 #[derive(Debug, Parser)]
 #[command(version, about)]
@@ -12,18 +17,104 @@ pub struct Cli {
 
 #[derive(Debug, Args)]
 pub struct ContainerSet {
-    /// One or more containers; can use names and IDs interchangeably.
+    /// One or more containers; can use names, IDs and @group selectors interchangeably.
+    #[arg(add = ArgValueCandidates::new(complete::containers))]
     pub containers: Vec<String>,
     /// Whether or not to operate on *all* containers.
     #[arg(short, long)]
     pub all: bool,
 }
 
+#[derive(Debug, Clone, Copy, Default, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// Human-readable table.
+    #[default]
+    Table,
+    /// Machine-readable JSON.
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+#[derive(Debug, Args)]
+pub struct ExecOptions {
+    /// The user (name or UID, with an optional group) to run as.
+    #[arg(short, long, conflicts_with = "root")]
+    pub user: Option<String>,
+    /// Run as root, regardless of the image's configured user.
+    #[arg(long)]
+    pub root: bool,
+    /// Set an environment variable (KEY=VALUE, or KEY to copy from the host.)
+    #[arg(short, long)]
+    pub env: Vec<String>,
+    /// Read environment variables from a file on the host.
+    #[arg(long)]
+    pub env_file: Vec<String>,
+    /// The working directory inside the container.
+    #[arg(short, long)]
+    pub workdir: Option<String>,
+}
+
+/// One-off additions to an image's runtime configuration, applied when a container is created.
+#[derive(Debug, Clone, Default, Args, Serialize, Deserialize)]
+pub struct Overrides {
+    /// Add a mount, in --mount syntax.
+    #[arg(long, value_parser = cfg::parser("mount"))]
+    pub mount: Vec<String>,
+    /// Set an environment variable (KEY=VALUE, or KEY to copy from the host.)
+    #[arg(short, long, value_parser = cfg::parser("env"))]
+    pub env: Vec<String>,
+    /// Publish a container port to the host ([[ip:][host]:]container[/protocol])
+    #[arg(short, long, value_parser = cfg::parser("publish"))]
+    pub publish: Vec<String>,
+    /// Pass an additional argument to podman run verbatim.
+    #[arg(long, allow_hyphen_values = true)]
+    pub arg: Vec<String>,
+    /// Leave out mounts added by presets (e.g. ssh-agent.)
+    #[arg(long)]
+    pub no_preset_mounts: bool,
+}
+
+impl Overrides {
+    /// Whether or not there is nothing to override.
+    pub fn is_empty(&self) -> bool {
+        self.mount.is_empty() &&
+        self.env.is_empty() &&
+        self.publish.is_empty() &&
+        self.arg.is_empty() &&
+        !self.no_preset_mounts
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct Wait {
+    /// Wait until the container(s) are healthy (or running, if they have no health check.)
+    #[arg(long)]
+    pub wait: bool,
+    /// How long to wait for, in seconds.
+    #[arg(long, default_value_t = 60, requires = "wait")]
+    pub timeout: u64,
+}
+
 #[derive(Debug, Subcommand)]
 pub enum Command {
+    /// Manage whether or not containers are started at login.
+    Autostart {
+        #[command(subcommand)]
+        operation: Autostart,
+    },
     /// Compile definitions into container images.
+    /// 
+    /// If no definitions are provided, one can be chosen interactively.
     Build {
         /// The definitions to build.
+        #[arg(add = ArgValueCandidates::new(complete::definitions))]
         defs: Vec<String>,
         /// Whether or not to operate on all definitions.
         #[arg(short, long)]
@@ -31,56 +122,209 @@ pub enum Command {
         /// Whether or not to ignore unchanged definitions.
         #[arg(short, long)]
         force: bool,
+        /// Re-run every step, rather than restoring the working container from build checkpoints.
+        #[arg(long)]
+        no_cache: bool,
+        /// Keep the working container if a step fails, so it can be investigated with `bx debug`.
+        #[arg(long)]
+        keep_failed: bool,
+    },
+    /// Print a script that registers shell completions.
+    ///
+    /// For example, add `bx completions fish | source` to your fish configuration,
+    /// or `source <(bx completions bash)` to your .bashrc.
+    Completions {
+        #[arg(value_enum)]
+        shell: Shell,
     },
     /// List all managed containers (alias: ls)
     #[clap(alias = "ls")]
     Containers,
     /// Create a new container definition.
     Create { name: String },
+    /// Open a shell in the working container kept by a failed `bx build --keep-failed`.
+    /// 
+    /// The shell is started with the same options (such as mounts) as the step that failed.
+    Debug {
+        #[arg(add = ArgValueCandidates::new(complete::definitions))]
+        name: Option<String>,
+        /// Remove the kept working container instead.
+        #[arg(long)]
+        discard: bool,
+    },
     /// List all managed definitions (alias: lsd)
     #[clap(alias = "lsd")]
     Definitions,
     /// Delete a container definition.
-    Delete { name: String, #[arg(short, long)] yes: bool },
+    /// 
+    /// If no name is provided, one can be chosen interactively. Otherwise, the name must be exact.
+    Delete { #[arg(add = ArgValueCandidates::new(complete::definitions))] name: Option<String>, #[arg(short, long)] yes: bool },
     /// Output the directory currently being used for definitions.
     Directory,
     /// Remove managed container(s).
-    Down    (ContainerSet),
+    /// 
+    /// Names must be exact (prefixes and globs aren't accepted.)
+    Down {
+        #[command(flatten)]
+        set: ContainerSet,
+        /// Also remove any volumes created by Box for the container(s), unless other containers still mount them.
+        #[arg(long)]
+        volumes: bool,
+    },
     /// Edit an existing container definition.
-    Edit   { name: String },
+    /// 
+    /// If no name is provided, one can be chosen interactively.
+    Edit   { #[arg(add = ArgValueCandidates::new(complete::definitions))] name: Option<String> },
     /// Invoke $SHELL inside a container.
-    Enter  { name: String },
+    /// 
+    /// If no name is provided, one can be chosen interactively.
+    Enter {
+        /// The name of the container, or - for the most recently used one.
+        #[arg(add = ArgValueCandidates::new(complete::containers))]
+        name: Option<String>,
+        #[command(flatten)]
+        options: ExecOptions,
+        /// The shell to use instead of the container's $SHELL.
+        #[arg(long)]
+        shell: Option<String>,
+        /// Start the shell as a login shell.
+        #[arg(short, long)]
+        login: bool,
+    },
     /// Execute a command inside a new ephemeral container.
     Ephemeral {
         /// The name or ID of the image to use.
+        #[arg(add = ArgValueCandidates::new(complete::images))]
+        name: String,
+        #[command(flatten)]
+        overrides: Overrides,
+        /// The program to execute, followed by its arguments (if any.)
+        /// 
+        /// Options after the program are passed to it rather than interpreted by Box.
+        #[arg(value_name = "PATH", required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+    /// Export a command from a container to the host, as a wrapper in ~/.local/bin.
+    ExportBin {
+        /// The name of the container.
+        #[arg(add = ArgValueCandidates::new(complete::containers))]
         name: String,
-        /// The program to execute.
-        path: String,
-        /// Arguments to the program, if any.
-        #[arg(allow_hyphen_values = true)]
-        args: Vec<String>,
+        /// The command to export.
+        command: String,
+        /// The name to export the command as; defaults to the command's file name.
+        #[arg(long = "name", value_name = "NAME")]
+        rename: Option<String>,
+    },
+    /// Export a desktop application (and its icon) from a container to the host.
+    ExportApp {
+        /// The name of the container.
+        #[arg(add = ArgValueCandidates::new(complete::containers))]
+        name: String,
+        /// The desktop file to export, by name or absolute path inside the container.
+        entry: String,
     },
     /// Execute a command inside a container.
+    /// 
+    /// If only a program is provided, the container can be chosen interactively.
     Exec {
-        /// The name of the container.
+        /// The name of the container, or - for the most recently used one.
+        #[arg(add = ArgValueCandidates::new(complete::containers))]
+        name: Option<String>,
+        #[command(flatten)]
+        options: ExecOptions,
+        /// Run the program in the background and print the session ID.
+        #[arg(short, long)]
+        detach: bool,
+        /// The program to execute, followed by its arguments (if any.)
+        /// 
+        /// Options after the program are passed to it rather than interpreted by Box.
+        #[arg(value_name = "PATH", trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+    /// List all groups of managed images.
+    Groups,
+    /// Show the fully resolved runtime configuration of an image and its container.
+    Inspect { #[arg(add = ArgValueCandidates::new(complete::images))] name: String },
+    /// Generate Kubernetes Pod manifests for image(s).
+    Kube {
+        /// One or more images; can use names and IDs interchangeably.
+        #[arg(add = ArgValueCandidates::new(complete::images))]
+        images: Vec<String>,
+        /// Whether or not to operate on *all* images.
+        #[arg(short, long)]
+        all: bool,
+        /// Where to write the manifests; defaults to standard output.
+        #[arg(short, long)]
+        output: Option<std::path::PathBuf>,
+    },
+    /// Show the output of managed container(s).
+    Logs {
+        #[command(flatten)]
+        set: ContainerSet,
+        /// Keep streaming new output.
+        #[arg(short, long)]
+        follow: bool,
+        /// Only show output since the given timestamp or duration (e.g. 10m.)
+        #[arg(long)]
+        since: Option<String>,
+    },
+    /// Inspect the networks managed containers are attached to.
+    Network {
+        #[command(subcommand)]
+        operation: Network,
+    },
+    /// Generate a Quadlet (systemd) unit for an image (alias: systemd)
+    #[clap(alias = "systemd")]
+    Quadlet {
+        /// The name or ID of the image to use.
+        #[arg(add = ArgValueCandidates::new(complete::images))]
         name: String,
-        /// The program to execute.
-        path: String,
-        /// Arguments to the program, if any.
-        #[arg(allow_hyphen_values = true)]
-        args: Vec<String>,
+        /// Print the unit to standard output instead of installing it.
+        #[arg(short, long)]
+        print: bool,
     },
     /// Restart managed container(s).
     Restart (ContainerSet),
     /// Remove and re-create managed container(s).
-    Reup    (ContainerSet),
+    /// 
+    /// Overrides the containers were created with are applied again, unless new ones are provided.
+    Reup {
+        #[command(flatten)]
+        set: ContainerSet,
+        #[command(flatten)]
+        overrides: Overrides,
+        #[command(flatten)]
+        wait: Wait,
+    },
+    /// View and validate Box's own settings.
+    /// 
+    /// These are read from config.toml in Box's configuration directory ($XDG_CONFIG_HOME/box or $HOME/.config/box.)
+    Settings,
     /// Start managed container(s).
-    Start   (ContainerSet),
+    Start {
+        #[command(flatten)]
+        set: ContainerSet,
+        #[command(flatten)]
+        wait: Wait,
+    },
     /// Stop managed containers(s).
     Stop    (ContainerSet),
+    /// List all volumes created by Box (alias: lsv)
+    #[clap(alias = "lsv")]
+    Volumes,
+    /// Show live resource usage of managed containers (alias: stats)
+    #[clap(alias = "stats")]
+    Top {
+        /// Output format (defaults to the one in Box's settings); JSON output is printed once rather than refreshed.
+        #[arg(long, value_enum)]
+        format: Option<Format>,
+    },
     /// Create managed container(s).
+    /// 
+    /// If no images are provided, one can be chosen interactively.
     Up {
-        /// One or more images; can use names and IDs interchangeably.
+        /// One or more images; can use names, IDs and @group selectors interchangeably.
+        #[arg(add = ArgValueCandidates::new(complete::images_and_groups))]
         containers: Vec<String>,
         /// Whether or not to operate on *all* images.
         #[arg(short, long)]
@@ -88,6 +332,10 @@ pub enum Command {
         /// Whether or not to replace existing containers.
         #[arg(short, long)]
         replace: bool,
+        #[command(flatten)]
+        overrides: Overrides,
+        #[command(flatten)]
+        wait: Wait,
     },
 
     #[clap(hide = true)]
@@ -96,8 +344,24 @@ pub enum Command {
     },
     #[clap(hide = true)]
     Config {
+        #[arg(add = ArgValueCandidates::new(complete::options))]
         operation : String,
-        #[arg(allow_hyphen_values = true)]
+        #[arg(allow_hyphen_values = true, add = ArgValueCandidates::new(complete::presets))]
         args      : Vec<String>,
     }
 }
+
+#[derive(Debug, Subcommand)]
+pub enum Autostart {
+    /// Install a Quadlet unit that starts the container at login.
+    Enable { #[arg(add = ArgValueCandidates::new(complete::images))] name: String },
+    /// Remove the Quadlet unit for the container.
+    Disable { #[arg(add = ArgValueCandidates::new(complete::containers))] name: String },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Network {
+    /// List networks and the managed containers attached to them.
+    #[clap(alias = "list")]
+    Ls,
+}
@@ -0,0 +1,92 @@
+//! Dynamic candidates for shell completion.
+//!
+//! These are invoked by the completion engine when the shell calls back into `bx`,
+//! so any failure (e.g. Podman being unavailable) simply yields no candidates.
+use std::collections::BTreeSet;
+
+use clap_complete::engine::CompletionCandidate;
+
+use crate::build::*;
+use crate::cfg::*;
+use crate::podman::*;
+
+/// Names of managed containers, plus `@group` selectors.
+pub fn containers() -> Vec<CompletionCandidate> {
+    let names = Container::enumerate()
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|c| c.annotation("box.name").map(str::to_owned))
+        .map(CompletionCandidate::new);
+
+    names
+        .chain(groups())
+        .collect()
+}
+
+/// Names of managed images.
+pub fn images() -> Vec<CompletionCandidate> {
+    Image::enumerate()
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|i| i.annotation("box.name").map(str::to_owned))
+        .map(CompletionCandidate::new)
+        .collect()
+}
+
+/// Names of managed images, plus `@group` selectors.
+pub fn images_and_groups() -> Vec<CompletionCandidate> {
+    images()
+        .into_iter()
+        .chain(groups())
+        .collect()
+}
+
+/// Names of definitions.
+pub fn definitions() -> Vec<CompletionCandidate> {
+    Definition::enumerate()
+        .unwrap_or_default()
+        .iter()
+        .map(|d| CompletionCandidate::new(d.name()))
+        .collect()
+}
+
+/// Configuration operations and the options that can be set with `CFG`.
+pub fn options() -> Vec<CompletionCandidate> {
+    let operations = ["run", "add", "commit", "preset"]
+        .into_iter()
+        .map(CompletionCandidate::new);
+
+    let options = ANNOTATIONS
+        .iter()
+        .map(|a| {
+            CompletionCandidate::new(a.name)
+                .help(Some(a.help.into()))
+        });
+
+    operations
+        .chain(options)
+        .collect()
+}
+
+/// Names of the available presets.
+pub fn presets() -> Vec<CompletionCandidate> {
+    PRESETS
+        .iter()
+        .map(|(name, help)| {
+            CompletionCandidate::new(name)
+                .help(Some((*help).into()))
+        })
+        .collect()
+}
+
+fn groups() -> impl Iterator<Item = CompletionCandidate> {
+    Image::enumerate()
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|i| i.annotation("box.group"))
+        .filter(|g| !g.is_empty())
+        .map(|g| format!("@{g}"))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .map(CompletionCandidate::new)
+}
@@ -0,0 +1,371 @@
+//! Exporting commands and desktop applications from containers to the host.
+//!
+//! Exports are marked with the name of the container they came from, so they can be found
+//! and removed again when the container (or its definition) goes away.
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::prelude::*;
+use crate::podman::*;
+use crate::settings::podman;
+use crate::CommandExt;
+
+/// Marks wrapper scripts with the container they were exported from.
+const MARKER: &str = "# box-export: ";
+
+/// Marks desktop entries with the container they were exported from.
+const APP_MARKER: &str = "X-Box-Export=";
+
+/// Directories searched (in order) for desktop entries inside a container.
+const APPLICATION_DIRS: &[&str] = &[
+    "/usr/local/share/applications",
+    "/usr/share/applications",
+];
+
+/// Export a command from the container as a wrapper script in `~/.local/bin`, returning the path of the script.
+///
+/// The wrapper runs the command through `bx exec` (starting the container if needed) in the
+/// same working directory as the caller, falling back to the container's default if it doesn't exist there.
+pub fn bin(ctr: &Container, command: &str, rename: Option<&str>) -> Result<PathBuf> {
+    let name = ctr.annotation("box.name")
+        .context("Container is missing its name annotation")?;
+
+    let resolved = run_in(ctr, r#"command -v "$1""#, &[command])
+        .with_context(|| format!("Could not find command {command} inside container {name}"))
+        .suggestion("Is it installed, and on the $PATH of the container's user?")?;
+
+    let export_as = match rename {
+        Some(export_as) => export_as,
+        None => Path::new(command)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .context("Command does not have a valid file name")?
+    };
+
+    let dir  = bin_directory()?;
+    let path = dir.join(export_as);
+
+    if path.exists() && exported_from(&path)?.as_deref() != Some(name) {
+        let err = eyre!("{} already exists and was not exported from {name}", path.to_string_lossy())
+            .suggestion("Use --name to export the command under a different name.");
+
+        return Err(err)
+    }
+
+    let script = format!(
+        "#!/bin/sh\n\
+        # Exported from {name} by Box; removed by 'bx down' and 'bx delete'.\n\
+        {MARKER}{name}\n\
+        exec {} exec {} sh -c 'cd \"$1\" 2>/dev/null; shift; exec \"$@\"' sh \"$PWD\" {} \"$@\"\n",
+        crate::shell_join(&[bx()?]),
+        crate::shell_join(&[name.to_owned()]),
+        crate::shell_join(&[resolved.trim().to_owned()])
+    );
+
+    fs::write(&path, script)
+        .context("Fault when writing exported command")?;
+
+    set_executable(&path)?;
+
+    let on_path = std::env::var_os("PATH")
+        .is_some_and(|p| std::env::split_paths(&p).any(|p| p == dir));
+
+    if !on_path {
+        let dir = dir.to_string_lossy();
+
+        crate::warning(
+            "directory",
+            &dir,
+            "is not on your $PATH, so exported commands will not be found",
+            Some(&format!("Add it to $PATH in your shell's configuration (e.g. export PATH=\"{dir}:$PATH\")."))
+        );
+    }
+
+    Ok(path)
+}
+
+/// Export a desktop entry (and its icon) from the container, returning the path of the new entry.
+///
+/// `entry` may be the name of a desktop file (with or without the `.desktop` extension)
+/// or an absolute path inside the container.
+pub fn app(ctr: &Container, entry: &str) -> Result<PathBuf> {
+    let name = ctr.annotation("box.name")
+        .context("Container is missing its name annotation")?;
+
+    let source = match entry.starts_with('/') {
+        true  => entry.to_owned(),
+        false => {
+            let file = match entry.ends_with(".desktop") {
+                true  => entry.to_owned(),
+                false => format!("{entry}.desktop")
+            };
+
+            let script = format!(
+                r#"for d in {}; do [ -f "$d/$1" ] && echo "$d/$1" && exit 0; done; exit 1"#,
+                APPLICATION_DIRS.join(" ")
+            );
+
+            run_in(ctr, &script, &[&file])
+                .with_context(|| format!("Could not find desktop entry {file} inside container {name}"))
+                .suggestion(format!("Box checked in {}", APPLICATION_DIRS.join(", ")))?
+                .trim()
+                .to_owned()
+        }
+    };
+
+    let contents = run_in(ctr, r#"cat "$1""#, &[&source])
+        .context("Fault when reading desktop entry from container")?;
+
+    let icon = contents
+        .lines()
+        .find_map(|l| l.strip_prefix("Icon="))
+        .map(str::trim)
+        .and_then(|icon| {
+            export_icon(ctr, name, icon)
+                .inspect_err(|e| crate::warning(
+                    "icon",
+                    icon,
+                    "could not be exported, so the desktop entry will use a generic icon",
+                    Some(&e.to_string())
+                ))
+                .ok()
+                .flatten()
+        });
+
+    let file_name = Path::new(&source)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .context("Desktop entry does not have a valid file name")?;
+
+    let dir = data_directory()?.join("applications");
+
+    fs::create_dir_all(&dir)
+        .context("Failed to create application directory")?;
+
+    let path = dir.join(
+        format!("box-{name}-{file_name}")
+    );
+
+    fs::write(&path, rewrite_entry(&contents, name, &bx()?, icon.as_deref()))
+        .context("Fault when writing exported desktop entry")?;
+
+    Ok(path)
+}
+
+/// Remove all commands, desktop entries and icons exported from the container with the provided name.
+pub fn remove(name: &str) -> Result<()> {
+    let app_marker = format!("{APP_MARKER}{name}");
+
+    let dirs = [
+        (bin_directory()?, format!("{MARKER}{name}")),
+        (data_directory()?.join("applications"), app_marker),
+    ];
+
+    for (dir, marker) in dirs {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+
+        for entry in entries {
+            let path = entry
+                .context("Fault when iterating over export directory")?
+                .path();
+
+            // Anything that isn't readable as text can't be one of ours.
+            let Ok(contents) = fs::read_to_string(&path) else {
+                continue;
+            };
+
+            if contents.lines().any(|l| l == marker) {
+                fs::remove_file(&path)
+                    .context("Fault when removing export")?;
+            }
+        }
+    }
+
+    let icons = icon_directory(name)?;
+
+    if icons.exists() {
+        fs::remove_dir_all(icons)
+            .context("Fault when removing exported icons")?;
+    }
+
+    Ok(())
+}
+
+/// Rewrite a desktop entry so it launches inside the container.
+fn rewrite_entry(contents: &str, name: &str, bx: &str, icon: Option<&Path>) -> String {
+    let bx = match bx.contains(char::is_whitespace) {
+        true  => format!("\"{bx}\""),
+        false => bx.to_owned()
+    };
+
+    let mut out = String::new();
+    let mut main_group = false;
+
+    for line in contents.lines() {
+        if line.starts_with('[') {
+            main_group = line.trim() == "[Desktop Entry]";
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .map_or((line, ""), |(k, v)| (k.trim(), v));
+
+        let line = match key {
+            "Exec" => format!("Exec={bx} exec {name} {value}"),
+            // Both of these would be evaluated on the host, not in the container.
+            "TryExec" | "DBusActivatable" => continue,
+            "Name" if main_group => format!("Name={value} ({name})"),
+            "Icon" => match icon {
+                Some(icon) => format!("Icon={}", icon.to_string_lossy()),
+                None => line.to_owned()
+            },
+            _ => line.to_owned()
+        };
+
+        out.push_str(&line);
+        out.push('\n');
+
+        if main_group && line.trim() == "[Desktop Entry]" {
+            out.push_str(&format!("{APP_MARKER}{name}\n"));
+        }
+    }
+
+    out
+}
+
+/// Copy an icon out of the container, returning its path on the host (or `None` if it couldn't be found.)
+fn export_icon(ctr: &Container, name: &str, icon: &str) -> Result<Option<PathBuf>> {
+    let script = r#"
+        case "$1" in
+            /*) [ -f "$1" ] && echo "$1" && exit 0 ;;
+        esac
+
+        for size in scalable 512x512 256x256 128x128 96x96 64x64 48x48 32x32; do
+            for ext in svg png; do
+                f="/usr/share/icons/hicolor/$size/apps/$1.$ext"
+                [ -f "$f" ] && echo "$f" && exit 0
+            done
+        done
+
+        for ext in svg png xpm; do
+            f="/usr/share/pixmaps/$1.$ext"
+            [ -f "$f" ] && echo "$f" && exit 0
+        done
+
+        exit 1
+    "#;
+
+    let Ok(source) = run_in(ctr, script, &[icon]) else {
+        return Ok(None)
+    };
+
+    let source = source.trim();
+
+    let file_name = Path::new(source)
+        .file_name()
+        .context("Icon does not have a valid file name")?;
+
+    let dir = icon_directory(name)?;
+
+    fs::create_dir_all(&dir)
+        .context("Failed to create icon directory")?;
+
+    let path = dir.join(file_name);
+
+    podman()
+        .arg("cp")
+        .arg(format!("{}:{source}", ctr.id))
+        .arg(&path)
+        .output_ok()
+        .context("Fault when copying icon from container")?;
+
+    Ok(Some(path))
+}
+
+/// Determine which container (if any) the wrapper script at the provided path was exported from.
+fn exported_from(path: &Path) -> Result<Option<String>> {
+    let Ok(contents) = fs::read_to_string(path) else {
+        return Ok(None)
+    };
+
+    let name = contents
+        .lines()
+        .find_map(|l| l.strip_prefix(MARKER))
+        .map(str::to_owned);
+
+    Ok(name)
+}
+
+/// Run a shell snippet inside the container, returning its standard output.
+fn run_in(ctr: &Container, script: &str, args: &[&str]) -> Result<String> {
+    podman()
+        .arg("exec")
+        .arg(&ctr.id)
+        .arg("sh")
+        .arg("-c")
+        .arg(script)
+        .arg("sh")
+        .args(args)
+        .output_ok()
+}
+
+fn set_executable(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755))
+        .context("Fault when marking exported command as executable")
+}
+
+/// The path to the running `bx` executable, so exports keep working even if it isn't on the `$PATH`.
+fn bx() -> Result<String> {
+    let path = std::env::current_exe()
+        .context("Could not determine the path to the bx executable")?;
+
+    Ok(
+        path.to_string_lossy().into_owned()
+    )
+}
+
+/// Determines the directory commands are exported to, creating it if necessary.
+///
+/// - `$HOME/.local/bin`
+fn bin_directory() -> Result<PathBuf> {
+    let Ok(home) = std::env::var("HOME") else {
+        let err = eyre!("Could not find a valid directory for exported commands")
+            .suggestion("Make sure $HOME is set.");
+
+        return Err(err)
+    };
+
+    let dir = PathBuf::from(home)
+        .join(".local")
+        .join("bin");
+
+    if !dir.exists() {
+        fs::create_dir_all(&dir)
+            .context("Failed to create directory for exported commands")?;
+    }
+
+    Ok(dir)
+}
+
+/// Determines the user's data directory.
+///
+/// - `$XDG_DATA_HOME`
+/// - `$HOME/.local/share`
+fn data_directory() -> Result<PathBuf> {
+    crate::build::xdg_home("XDG_DATA_HOME", ".local/share")
+        .context("Could not find a valid directory for exported applications")
+}
+
+/// The directory icons exported from the container with the provided name are kept in.
+fn icon_directory(name: &str) -> Result<PathBuf> {
+    Ok(
+        data_directory()?
+            .join("box")
+            .join("icons")
+            .join(name)
+    )
+}
@@ -0,0 +1,106 @@
+//! Working containers kept after a failed build (`bx build --keep-failed`), for debugging with `bx debug`.
+//!
+//! Each failure is recorded in `$XDG_STATE_HOME/box/failed/<definition>.json`.
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::prelude::*;
+use crate::podman::exit_code;
+use crate::settings::buildah;
+use crate::CommandExt;
+
+/// A working container kept after a directive failed.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Failure {
+    /// The name of the working container.
+    pub container : String,
+    /// The directive that failed (e.g. `["run", "--", "make"]`.)
+    pub directive : Vec<String>,
+    /// The `buildah run` options (such as mounts) the directive was run with.
+    pub options   : Vec<String>,
+}
+
+impl Failure {
+    /// Record that a directive failed in the working container of the named definition.
+    ///
+    /// Any working container kept by an earlier failure of the same definition is removed.
+    pub fn record(name: &str, failure: Self) -> Result<()> {
+        if let Ok(old) = Self::load(name) {
+            if old.container != failure.container {
+                old.remove();
+            }
+        }
+
+        let json = serde_json::to_string(&failure)
+            .context("Fault when serializing build failure")?;
+
+        std::fs::write(failure_path(name)?, json)
+            .context("Fault when recording build failure")?;
+
+        Ok(())
+    }
+
+    /// Load the recorded failure of the named definition.
+    pub fn load(name: &str) -> Result<Self> {
+        let path = failure_path(name)?;
+
+        if !path.exists() {
+            let err = eyre!("No failed build of {name} has been kept")
+                .suggestion(format!("Run 'bx build --keep-failed {name}' to keep the working container if it fails."));
+
+            return Err(err)
+        }
+
+        let contents = std::fs::read_to_string(&path)
+            .context("Fault when reading build failure")?;
+
+        serde_json::from_str(&contents)
+            .context("Failed to deserialize build failure")
+    }
+
+    /// Open an interactive shell in the kept working container, with the same options as the failed directive.
+    ///
+    /// Returns the exit code of the shell.
+    pub fn shell(&self) -> Result<i32> {
+        let mut c = buildah();
+
+        c
+            .args(["run", "-t"])
+            .args(&self.options)
+            .arg(&self.container)
+            .args(["--", "sh", "-c", "command -v bash >/dev/null && exec bash; exec sh"]);
+
+        exit_code(&mut c)
+            .context("Fault when opening shell in working container")
+    }
+
+    /// Remove the kept working container, and forget about the failure.
+    pub fn discard(name: &str) -> Result<()> {
+        Self::load(name)?.remove();
+
+        std::fs::remove_file(failure_path(name)?)
+            .context("Fault when removing build failure record")
+    }
+
+    fn remove(&self) {
+        // The container may have already been removed by hand.
+        if let Err(err) = buildah().args(["rm", &self.container]).output_ok() {
+            warn!("Could not remove working container {}: {err}", self.container);
+        }
+    }
+}
+
+/// Determines where the failure of the named definition is recorded, creating the directory if necessary.
+fn failure_path(name: &str) -> Result<PathBuf> {
+    let dir = crate::state_directory()?.join("failed");
+
+    if !dir.exists() {
+        std::fs::create_dir_all(&dir)
+            .context("Failed to create directory for build failures")?;
+    }
+
+    Ok(
+        dir.join(format!("{name}.json"))
+    )
+}
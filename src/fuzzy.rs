@@ -1,6 +1,13 @@
-//! Basic string fuzzy-matching implementation based on the Levenshtein distance algorithm.
+//! Basic string fuzzy-matching implementation based on the (optimal string alignment)
+//! Damerau-Levenshtein distance algorithm.
 use std::collections::HashSet;
 
+/// How different (relative to its length) a name can be from the input and still be suggested.
+const THRESHOLD: f64 = 0.4;
+
+/// The most names that will be suggested at once.
+const MAX_SUGGESTIONS: usize = 3;
+
 #[derive(Debug, Clone, Default)]
 pub struct Fuzzy {
     set: HashSet<String>,
@@ -17,27 +24,86 @@ impl Fuzzy {
         );
     }
 
+    /// Compute the edit distance from the input to every name, closest first.
     pub fn find(&self, input: impl AsRef<str>) -> Vec<(usize, &str)> {
         let input   = input.as_ref();
         let mut out = vec![];
 
         for s in &self.set {
             let pair = (
-                Self::levenshtein(s, input),
+                Self::damerau(s, input),
                 s.as_str()
             );
 
             out.push(pair)
         }
 
-        out.sort_unstable_by_key(|s| s.0);
+        out.sort_unstable();
 
         out
     }
 
-    // Adapted from https://stackoverflow.com/a/9453762
-    fn levenshtein(a: &str, b: &str) -> usize {
+    /// Find the names similar enough to the input to be worth suggesting, best first.
+    /// 
+    /// Names are scored by their edit distance relative to their length; names that start with
+    /// (or contain) the input are given a boost.
+    pub fn similar(&self, input: impl AsRef<str>) -> Vec<&str> {
+        let input = input.as_ref();
+        let len   = input.chars().count();
+
+        let mut scored: Vec<_> = self
+            .find(input)
+            .into_iter()
+            .filter_map(|(distance, s)| {
+                let longest = len.max(s.chars().count()).max(1);
+
+                let mut score = distance as f64 / longest as f64;
+
+                if !input.is_empty() {
+                    if s.starts_with(input) || input.starts_with(s) {
+                        score *= 0.5;
+                    }
+                    else if s.contains(input) || input.contains(s) {
+                        score *= 0.7;
+                    }
+                }
+
+                (score <= THRESHOLD).then_some((score, s))
+            })
+            .collect();
+
+        scored.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(b.1)));
+
+        scored
+            .into_iter()
+            .take(MAX_SUGGESTIONS)
+            .map(|(_, s)| s)
+            .collect()
+    }
+
+    /// Describe the names similar to the input, for use in an error suggestion.
+    pub fn suggestion(&self, input: impl AsRef<str>) -> String {
+        let quoted: Vec<_> = self
+            .similar(input)
+            .into_iter()
+            .map(|s| format!("'{s}'"))
+            .collect();
+
+        match quoted.as_slice() {
+            [] => "There are no similar names - did you make a typo?".to_owned(),
+            [s] => format!("Did you mean {s}?"),
+            [rest @ .., last] => format!("Did you mean {} or {last}?", rest.join(", ")),
+        }
+    }
+
+    // Adapted from https://stackoverflow.com/a/9453762, keeping only the
+    // three most recent rows of the matrix.
+    fn damerau(a: &str, b: &str) -> usize {
         use std::cmp::{min, max};
+        use std::mem::swap;
+
+        let a: Vec<char> = a.chars().collect();
+        let b: Vec<char> = b.chars().collect();
 
         if a.is_empty() || b.is_empty() {
             return max(
@@ -45,31 +111,32 @@ impl Fuzzy {
             );
         }
 
-        #[allow(clippy::zero_repeat_side_effects)]
-        let mut distances = vec![
-            vec![0; b.len() + 1]; a.len() + 1
-        ];
-
-        #[allow(clippy::needless_range_loop)]
-        for i in 0..=a.len() { distances[i][0] = i; }
-        #[allow(clippy::needless_range_loop)]
-        for i in 0..=b.len() { distances[0][i] = i; }
-        
-        let b_a = a.as_bytes();
-        let b_b = b.as_bytes();
+        let mut before: Vec<usize> = vec![0; b.len() + 1];
+        let mut prev: Vec<usize>   = (0..=b.len()).collect();
+        let mut row: Vec<usize>    = vec![0; b.len() + 1];
 
         for i in 1..=a.len() {
+            row[0] = i;
+
             for j in 1..=b.len() {
-                let cost = if b_b[j - 1] == b_a[i - 1] { 0 } else { 1 };
+                let cost = usize::from(a[i - 1] != b[j - 1]);
 
-                distances[i][j] = min(
-                    min(distances[i - 1][j] + 1, distances[i][j - 1] + 1),
-                    distances[i - 1][j - 1] + cost
+                row[j] = min(
+                    min(prev[j] + 1, row[j - 1] + 1),
+                    prev[j - 1] + cost
                 );
+
+                // Adjacent transposition.
+                if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                    row[j] = min(row[j], before[j - 2] + 1);
+                }
             }
+
+            swap(&mut before, &mut prev);
+            swap(&mut prev, &mut row);
         }
 
-        distances[a.len()][b.len()]
+        prev[b.len()]
     }
 }
 
@@ -101,4 +168,56 @@ mod tests {
             r[4], (4, "ooo")
         );
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn unicode() {
+        assert_eq!(Fuzzy::damerau("café", "cafe"), 1);
+        assert_eq!(Fuzzy::damerau("日本語", "日本"), 1);
+    }
+
+    #[test]
+    fn transposition() {
+        assert_eq!(Fuzzy::damerau("abcd", "acbd"), 1);
+        assert_eq!(Fuzzy::damerau("dve", "dev"), 1);
+        assert_eq!(Fuzzy::damerau("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn similar() {
+        let mut f = Fuzzy::new();
+
+        f.add("devbox");
+        f.add("dev");
+        f.add("database");
+        f.add("web");
+
+        assert_eq!(
+            f.similar("dev"), ["dev", "devbox"]
+        );
+
+        assert_eq!(
+            f.similar("dve"), ["dev"]
+        );
+
+        assert!(
+            f.similar("zzz").is_empty()
+        );
+    }
+
+    #[test]
+    fn suggestion() {
+        let mut f = Fuzzy::new();
+
+        f.add("rust");
+        f.add("rusty");
+        f.add("ooo");
+
+        assert_eq!(
+            f.suggestion("rst"), "Did you mean 'rust' or 'rusty'?"
+        );
+
+        assert_eq!(
+            f.suggestion("python"), "There are no similar names - did you make a typo?"
+        );
+    }
+}
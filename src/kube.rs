@@ -0,0 +1,523 @@
+//! Translation of Box images into Kubernetes Pod manifests.
+use serde_json::{json, Map, Value};
+
+use crate::prelude::*;
+use crate::cfg::*;
+use crate::local::Local;
+use crate::podman::*;
+
+/// Render a multi-document Kubernetes YAML file containing one Pod per image.
+///
+/// Any runtime configuration that has no Kubernetes equivalent is reported as a warning.
+pub fn render(images: &[Image]) -> Result<String> {
+    let mut docs = vec![];
+
+    for image in images {
+        let name = image.annotation("box.name")
+            .context("Image is missing its name annotation")
+            .suggestion("Was it built by Box?")?;
+
+        let pod = Pod::new(image, &Local::load(name)?);
+
+        if !pod.warnings.is_empty() {
+            crate::warning(
+                "box",
+                name,
+                "has configuration that could not be fully translated",
+                Some(&pod.warnings.join("\n  "))
+            );
+        }
+
+        docs.push(
+            yaml(&pod.manifest, 0)
+        );
+    }
+
+    Ok(docs.join("---\n"))
+}
+
+/// Accumulator for the pieces of a Pod manifest.
+#[derive(Debug, Default)]
+struct Pod {
+    manifest : Value,
+    warnings : Vec<String>,
+}
+
+impl Pod {
+    /// Translate an image, with the same configuration 'bx up' would use on this host.
+    fn new(image: &Image, local: &Local) -> Self {
+        let mut pod = Self::default();
+
+        let name = image.annotation("box.name").unwrap_or("?");
+        let hash = image.annotation("box.hash").unwrap_or("?");
+
+        let mut env       = vec![];
+        let mut ports     = vec![];
+        let mut mounts    = vec![];
+        let mut volumes   = vec![];
+        let mut limits    = Map::new();
+        let mut caps_add  = vec![];
+        let mut caps_drop = vec![];
+        let mut security  = Map::new();
+        let mut spec      = Map::new();
+
+        for port in image.config.ports.iter().flat_map(|p| p.keys()) {
+            let (number, protocol) = port
+                .split_once('/')
+                .unwrap_or((port, "tcp"));
+
+            match number.parse::<u16>() {
+                Ok(number) => ports.push(json!({
+                    "containerPort" : number,
+                    "protocol"      : protocol.to_uppercase()
+                })),
+                Err(_) => pod.warn(format!("exposed port {port} is not a single port number"))
+            }
+        }
+
+        for a in ANNOTATIONS {
+            let values = image.configured_values(a.name, local);
+
+            match a.name {
+                "args" => pod.args(&values, &mut env, &mut ports, &mut security, &mut spec),
+                "mount" => for v in values {
+                    let idx = volumes.len();
+
+                    if let Some((volume, mount)) = pod.mount(name, idx, v) {
+                        volumes.push(volume);
+                        mounts.push(mount);
+                    }
+                },
+                "cpus" => for v in values {
+                    limits.insert("cpu".to_owned(), json!(v));
+                },
+                "memory" => for v in values {
+                    match quantity(v) {
+                        Some(q) => { limits.insert("memory".to_owned(), json!(q)); },
+                        None    => pod.warn(format!("memory limit {v} is not a valid quantity"))
+                    }
+                },
+                "env" => for v in values {
+                    match v.split_once('=') {
+                        Some((k, v)) => env.push(json!({ "name": k, "value": v })),
+                        None => pod.warn(format!("environment variable {v} is copied from the host, which has no Kubernetes equivalent"))
+                    }
+                },
+                "publish" => for v in values {
+                    match publish(v) {
+                        Some(port) => ports.push(port),
+                        None => pod.warn(format!("could not translate published port {v}"))
+                    }
+                },
+                "hostname" => for v in values {
+                    spec.insert("hostname".to_owned(), json!(v));
+                },
+                "cap-add"  => caps_add.extend(values),
+                "cap-drop" => caps_drop.extend(values),
+                "restart"  => for v in values {
+                    let policy = match v.split(':').next().unwrap_or(v) {
+                        "no"             => "Never",
+                        "on-failure"     => "OnFailure",
+                        "always"         => "Always",
+                        "unless-stopped" => "Always",
+                        _ => {
+                            pod.warn(format!("restart policy {v} has no Kubernetes equivalent"));
+                            continue;
+                        }
+                    };
+
+                    spec.insert("restartPolicy".to_owned(), json!(policy));
+                },
+                "security-opt" => for v in values {
+                    match v {
+                        "no-new-privileges" | "no-new-privileges=true" => {
+                            security.insert("allowPrivilegeEscalation".to_owned(), json!(false));
+                        },
+                        _ => pod.warn(format!("security-opt {v} has no Kubernetes equivalent"))
+                    }
+                },
+                o => for v in values {
+                    pod.warn(format!("{o} {v} has no Kubernetes equivalent"))
+                }
+            }
+        }
+
+        if !caps_add.is_empty() || !caps_drop.is_empty() {
+            let mut caps = Map::new();
+
+            if !caps_add.is_empty() {
+                caps.insert("add".to_owned(), json!(caps_add));
+            }
+
+            if !caps_drop.is_empty() {
+                caps.insert("drop".to_owned(), json!(caps_drop));
+            }
+
+            security.insert("capabilities".to_owned(), Value::Object(caps));
+        }
+
+        let mut container = Map::new();
+
+        container.insert("name".to_owned(), json!(dns_label(name)));
+        container.insert("image".to_owned(), json!(name));
+
+        let optional = [
+            ("env", json!(env)),
+            ("ports", json!(ports)),
+            ("volumeMounts", json!(mounts)),
+            ("resources", match limits.is_empty() {
+                true  => json!({}),
+                false => json!({ "limits": limits })
+            }),
+            ("securityContext", Value::Object(security)),
+        ];
+
+        for (key, value) in optional {
+            if !is_empty(&value) {
+                container.insert(key.to_owned(), value);
+            }
+        }
+
+        spec.insert("containers".to_owned(), json!([container]));
+
+        if !volumes.is_empty() {
+            spec.insert("volumes".to_owned(), json!(volumes));
+        }
+
+        pod.manifest = json!({
+            "apiVersion" : "v1",
+            "kind"       : "Pod",
+            "metadata"   : {
+                "name"        : dns_label(name),
+                "labels"      : { "app": dns_label(name) },
+                "annotations" : {
+                    "manager"  : "box",
+                    "box.name" : name,
+                    "box.hash" : hash,
+                }
+            },
+            "spec" : spec
+        });
+
+        pod
+    }
+
+    fn warn(&mut self, message: String) {
+        self.warnings.push(message)
+    }
+
+    /// Translate the subset of raw `podman run` arguments that Kubernetes can represent.
+    fn args(
+        &mut self,
+        values   : &[&str],
+        env      : &mut Vec<Value>,
+        ports    : &mut Vec<Value>,
+        security : &mut Map<String, Value>,
+        spec     : &mut Map<String, Value>
+    ) {
+        let mut iter = values.iter();
+
+        while let Some(arg) = iter.next() {
+            let (flag, inline) = match arg.split_once('=') {
+                Some((f, v)) if f.starts_with('-') => (f, Some(v)),
+                _ => (*arg, None)
+            };
+
+            let mut value = || inline.or_else(|| iter.next().copied());
+
+            match flag {
+                "--privileged" => {
+                    security.insert("privileged".to_owned(), json!(true));
+                },
+                "-e" | "--env" => match value().and_then(|v| v.split_once('=')) {
+                    Some((k, v)) => env.push(json!({ "name": k, "value": v })),
+                    None => self.warn(format!("environment argument {arg} has no value"))
+                },
+                "-p" | "--publish" => match value().and_then(publish) {
+                    Some(port) => ports.push(port),
+                    None => self.warn(format!("could not translate published port {arg}"))
+                },
+                "--net" | "--network" => match value() {
+                    Some("host") => { spec.insert("hostNetwork".to_owned(), json!(true)); },
+                    v => self.warn(format!("network {} has no Kubernetes equivalent", v.unwrap_or("?")))
+                },
+                "-u" | "--user" => {
+                    let v = value().unwrap_or_default();
+                    let (user, group) = v.split_once(':').unwrap_or((v, ""));
+
+                    match (user.parse::<u32>(), group.parse::<u32>()) {
+                        (Ok(u), Ok(g)) => {
+                            security.insert("runAsUser".to_owned(), json!(u));
+                            security.insert("runAsGroup".to_owned(), json!(g));
+                        },
+                        (Ok(u), _) if group.is_empty() => {
+                            security.insert("runAsUser".to_owned(), json!(u));
+                        },
+                        _ => self.warn(format!("user {v} must be numeric to be translated"))
+                    }
+                },
+                _ => self.warn(format!("argument {arg} has no Kubernetes equivalent"))
+            }
+        }
+    }
+
+    /// Translate a `--mount` specification into a volume and a volume mount.
+    fn mount(&mut self, name: &str, idx: usize, spec: &str) -> Option<(Value, Value)> {
+        let mut kind = "volume";
+        let mut src  = None;
+        let mut dst  = None;
+        let mut ro   = false;
+
+        for option in spec.split(',') {
+            let (k, v) = option
+                .split_once('=')
+                .unwrap_or((option, "true"));
+
+            match k {
+                "type" => kind = v,
+                "src" | "source" => src = Some(v),
+                "dst" | "destination" | "target" => dst = Some(v),
+                "ro" | "readonly" => ro = v == "true",
+                _ => ()
+            }
+        }
+
+        let Some(dst) = dst else {
+            self.warn(format!("mount {spec} has no destination"));
+            return None
+        };
+
+        let volume_name = format!("{}-vol-{idx}", dns_label(name));
+
+        let volume = match (kind, src) {
+            ("bind", Some(src)) => json!({
+                "name"     : volume_name,
+                "hostPath" : { "path": src }
+            }),
+            ("tmpfs", _) => json!({
+                "name"     : volume_name,
+                "emptyDir" : { "medium": "Memory" }
+            }),
+            _ => {
+                self.warn(format!("mount {spec} has no Kubernetes equivalent"));
+                return None
+            }
+        };
+
+        let mount = json!({
+            "name"      : volume_name,
+            "mountPath" : dst,
+            "readOnly"  : ro
+        });
+
+        Some((volume, mount))
+    }
+}
+
+/// Translate a `--publish` specification (`[[ip:]host:]container[/protocol]`) into a container port.
+fn publish(spec: &str) -> Option<Value> {
+    let (spec, protocol) = spec
+        .split_once('/')
+        .unwrap_or((spec, "tcp"));
+
+    let mut parts = spec.rsplit(':');
+
+    let container = parts.next()?.parse::<u16>().ok()?;
+    let host      = parts.next().and_then(|p| p.parse::<u16>().ok());
+
+    let mut port = json!({
+        "containerPort" : container,
+        "protocol"      : protocol.to_uppercase()
+    });
+
+    if let Some(host) = host {
+        port["hostPort"] = json!(host);
+    }
+
+    Some(port)
+}
+
+/// Translate a Podman memory limit (`b`, `k`, `m` or `g` suffix) into a Kubernetes quantity.
+fn quantity(limit: &str) -> Option<String> {
+    let limit = limit.to_lowercase();
+
+    let (number, suffix) = match limit.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+        Some((idx, _)) => limit.split_at(idx),
+        None => (limit.as_str(), "")
+    };
+
+    number.parse::<u64>().ok()?;
+
+    let suffix = match suffix {
+        "" | "b" => "",
+        "k"      => "Ki",
+        "m"      => "Mi",
+        "g"      => "Gi",
+        _        => return None
+    };
+
+    Some(format!("{number}{suffix}"))
+}
+
+/// Coerce a name into a valid DNS label, as Kubernetes requires for most object names.
+fn dns_label(name: &str) -> String {
+    let label: String = name
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+
+    label
+        .trim_matches('-')
+        .to_owned()
+}
+
+fn is_empty(value: &Value) -> bool {
+    match value {
+        Value::Array(a)  => a.is_empty(),
+        Value::Object(o) => o.values().all(is_empty),
+        Value::Null      => true,
+        _                => false
+    }
+}
+
+/// Serialize a JSON value as block-style YAML. Strings are always emitted in (JSON-compatible)
+/// double-quoted form, so no YAML-specific escaping rules need to be considered.
+fn yaml(value: &Value, indent: usize) -> String {
+    let pad = " ".repeat(indent);
+
+    // Keys are only left unquoted when they can't be mistaken for anything but a string
+    // (including by YAML 1.1 parsers, which treat the likes of `yes` and `off` as booleans.)
+    let key = |k: &str| {
+        let plain = k.starts_with(|c: char| c.is_ascii_alphabetic())
+            && k.chars().all(|c| c.is_ascii_alphanumeric() || "-_./".contains(c))
+            && !matches!(
+                k.to_lowercase().as_str(),
+                "y" | "n" | "yes" | "no" | "on" | "off" | "true" | "false" | "null"
+            );
+
+        match plain {
+            true  => k.to_owned(),
+            false => Value::from(k).to_string()
+        }
+    };
+
+    match value {
+        Value::Object(map) if !map.is_empty() => map
+            .iter()
+            .map(|(k, v)| match v {
+                Value::Object(o) if !o.is_empty() => format!("{pad}{}:\n{}", key(k), yaml(v, indent + 2)),
+                Value::Array(a)  if !a.is_empty() => format!("{pad}{}:\n{}", key(k), yaml(v, indent)),
+                _ => format!("{pad}{}: {}", key(k), yaml(v, 0))
+            })
+            .collect(),
+        Value::Array(items) if !items.is_empty() => items
+            .iter()
+            .map(|item| match item {
+                // Render the item one level deeper, then swap the leading
+                // indentation of its first line for the list marker.
+                Value::Object(o) if !o.is_empty() => {
+                    let block = yaml(item, indent + 2);
+                    format!("{pad}- {}", &block[indent + 2..])
+                },
+                _ => format!("{pad}- {}", yaml(item, 0))
+            })
+            .collect(),
+        Value::Object(_) => "{}\n".to_owned(),
+        Value::Array(_)  => "[]\n".to_owned(),
+        scalar           => format!("{scalar}\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scalars() {
+        let value = json!({
+            "bool"      : "yes",
+            "no"        : "on",
+            "colon"     : "a: b",
+            "comment"   : "# c",
+            "dash"      : "- d",
+            "number"    : "123",
+            "float"     : "1e3",
+            "multiline" : "one\ntwo",
+            "1"         : 1,
+            "box.name"  : "web",
+        });
+
+        let expected = [
+            "\"1\": 1",
+            "bool: \"yes\"",
+            "box.name: \"web\"",
+            "colon: \"a: b\"",
+            "comment: \"# c\"",
+            "dash: \"- d\"",
+            "float: \"1e3\"",
+            "multiline: \"one\\ntwo\"",
+            "\"no\": \"on\"",
+            "number: \"123\"",
+        ];
+
+        assert_eq!(yaml(&value, 0), expected.join("\n") + "\n");
+    }
+
+    #[test]
+    fn blocks() {
+        let value = json!({
+            "spec": {
+                "containers": [
+                    { "name": "web", "args": ["-v", "--"] },
+                    "plain"
+                ],
+                "empty": {},
+                "none": []
+            }
+        });
+
+        let expected = concat!(
+            "spec:\n",
+            "  containers:\n",
+            "  - args:\n",
+            "    - \"-v\"\n",
+            "    - \"--\"\n",
+            "    name: \"web\"\n",
+            "  - \"plain\"\n",
+            "  empty: {}\n",
+            "  none: []\n",
+        );
+
+        assert_eq!(yaml(&value, 0), expected);
+    }
+
+    #[test]
+    fn quantities() {
+        assert_eq!(quantity("512m").as_deref(), Some("512Mi"));
+        assert_eq!(quantity("2G").as_deref(), Some("2Gi"));
+        assert_eq!(quantity("64k").as_deref(), Some("64Ki"));
+        assert_eq!(quantity("100").as_deref(), Some("100"));
+        assert_eq!(quantity("100b").as_deref(), Some("100"));
+
+        assert_eq!(quantity("1.5g"), None);
+        assert_eq!(quantity("g"), None);
+        assert_eq!(quantity("10t"), None);
+    }
+
+    #[test]
+    fn labels() {
+        assert_eq!(dns_label("web"), "web");
+        assert_eq!(dns_label("My_Box.2"), "my-box-2");
+        assert_eq!(dns_label("-edge-"), "edge");
+    }
+
+    #[test]
+    fn ports() {
+        assert_eq!(publish("80"), Some(json!({ "containerPort": 80, "protocol": "TCP" })));
+        assert_eq!(publish("8080:80"), Some(json!({ "containerPort": 80, "hostPort": 8080, "protocol": "TCP" })));
+        assert_eq!(publish("127.0.0.1:53:53/udp"), Some(json!({ "containerPort": 53, "hostPort": 53, "protocol": "UDP" })));
+        assert_eq!(publish("127.0.0.1::80"), Some(json!({ "containerPort": 80, "protocol": "TCP" })));
+        assert_eq!(publish("http"), None);
+    }
+}
@@ -0,0 +1,151 @@
+//! Per-host override files, which adjust the runtime configuration of an image on this machine only.
+//!
+//! Overrides live in `$XDG_CONFIG_HOME/box/local/<name>.toml` (or `$HOME/.config/box/local/<name>.toml`)
+//! and are applied when containers are created, so they never affect definition hashes or force a rebuild.
+//!
+//! ```toml
+//! [add]
+//! mount = ["type=bind,src=/mnt/data,dst=/data"]
+//!
+//! [replace]
+//! memory = "16g"
+//!
+//! [remove]
+//! device = "/dev/dri"
+//! ```
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::prelude::*;
+use crate::cfg::*;
+use crate::fuzzy::*;
+
+/// A single value, or a list of values.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Values {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Values {
+    pub fn as_slice(&self) -> &[String] {
+        match self {
+            Self::One(v)  => std::slice::from_ref(v),
+            Self::Many(v) => v,
+        }
+    }
+}
+
+/// The contents of an override file, keyed by `CFG` option name.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Local {
+    /// Values to add to an option.
+    #[serde(default)]
+    pub add     : BTreeMap<String, Values>,
+    /// Values that replace all of an option's values (an empty list removes them all.)
+    #[serde(default)]
+    pub replace : BTreeMap<String, Values>,
+    /// Specific values to remove from an option.
+    #[serde(default)]
+    pub remove  : BTreeMap<String, Values>,
+}
+
+impl Local {
+    /// Load and validate the override file for the image with the provided name, if one exists.
+    pub fn load(name: &str) -> Result<Self> {
+        let path = local_directory()?.join(
+            format!("{name}.toml")
+        );
+
+        if !path.exists() {
+            return Ok(Self::default())
+        }
+
+        let contents = std::fs::read_to_string(&path)
+            .context("Fault when reading override file")?;
+
+        let local: Self = toml::from_str(&contents)
+            .context("Failed to parse override file")
+            .with_section(|| path.to_string_lossy().into_owned().header("Path:"))?;
+
+        local
+            .validate()
+            .with_section(|| path.to_string_lossy().into_owned().header("Path:"))?;
+
+        Ok(local)
+    }
+
+    /// Apply the overrides for an option to its values from the image.
+    ///
+    /// Replacements are applied first, then removals, then additions.
+    pub fn apply<'a>(&'a self, option: &str, values: &mut Vec<&'a str>) {
+        if let Some(replace) = self.replace.get(option) {
+            *values = replace
+                .as_slice()
+                .iter()
+                .map(String::as_str)
+                .collect();
+        }
+
+        if let Some(remove) = self.remove.get(option) {
+            values.retain(|v| !remove.as_slice().iter().any(|r| r == v));
+        }
+
+        if let Some(add) = self.add.get(option) {
+            values.extend(
+                add.as_slice().iter().map(String::as_str)
+            );
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        let tables = [
+            ("add", &self.add),
+            ("replace", &self.replace),
+            ("remove", &self.remove),
+        ];
+
+        for (table, options) in tables {
+            for (name, values) in options {
+                let Some(option) = Annotation::find(name) else {
+                    let mut fuzzy = Fuzzy::new();
+
+                    for a in ANNOTATIONS { fuzzy.add(a.name); }
+
+                    let err = eyre!("Unknown configuration option {name} in [{table}]")
+                        .suggestion(fuzzy.suggestion(name));
+
+                    return Err(err)
+                };
+
+                if table == "remove" {
+                    continue;
+                }
+
+                for value in values.as_slice() {
+                    (option.validate)(value)
+                        .with_context(|| format!("Invalid value {value:?} for option {name} in [{table}]"))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Determines the directory override files are read from.
+///
+/// - `$XDG_CONFIG_HOME/box/local`
+/// - `$HOME/.config/box/local`
+fn local_directory() -> Result<PathBuf> {
+    Ok(
+        crate::build::config_home()
+            .context("Could not find a valid directory for override files")?
+            .join("box")
+            .join("local")
+    )
+}
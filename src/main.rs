@@ -1,7 +1,18 @@
 mod build;
+mod checkpoint;
+mod cfg;
 mod cli;
+mod complete;
+mod export;
+mod failed;
 mod fuzzy;
+mod kube;
+mod local;
+mod pick;
 mod podman;
+mod quadlet;
+mod resolve;
+mod settings;
 mod user;
 
 mod prelude {
@@ -27,11 +38,16 @@ mod prelude {
     };
 }
 
+use std::collections::{HashMap, HashSet};
+
 use prelude::*;
 use build::*;
+use cfg::*;
 use cli::*;
+use failed::Failure;
 use fuzzy::*;
 use podman::*;
+use settings::{buildah, podman, settings};
 
 #[cfg(not(target_os = "linux"))]
 compile_error!(
@@ -39,32 +55,50 @@ compile_error!(
 );
 
 fn main() -> Result<()> {
-    use clap::Parser;
-    use indicatif::{ProgressBar, ProgressStyle};
+    use std::io::IsTerminal;
+
+    use clap::{CommandFactory, Parser};
+    use clap_complete::CompleteEnv;
+    use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
     use Command::*;
 
+    // If we're being called back by a shell for completions, this handles it and exits.
+    CompleteEnv::with_factory(Cli::command).complete();
+
     let args = Cli::parse();
 
-    // Only use the verbosity flag if RUST_LOG isn't already set.
+    install_hooks();
+
+    // Settings are loaded first, as they can set the log level.
+    let settings = settings::init()?;
+
+    // Only use the verbosity flag (or the log setting) if RUST_LOG isn't already set.
     if std::env::var("RUST_LOG").is_err() {
         match args.verbose {
-            0 => std::env::set_var("RUST_LOG", "none"),
+            0 => std::env::set_var("RUST_LOG", settings.log.as_str()),
             1 => std::env::set_var("RUST_LOG", "info"),
             2 => std::env::set_var("RUST_LOG", "debug"),
             _ => std::env::set_var("RUST_LOG", "trace"),
         }
     }
 
-    install_logging();
+    env_logger::init();
 
     info!("Parsed arguments:\n{args:#?}");
 
-    ensure("podman")?;
-    ensure("buildah")?;
+    // Generating a completion script (or viewing settings) doesn't need Podman or Buildah.
+    if !matches!(args.command, Completions { .. } | Settings) {
+        ensure(&settings.podman)?;
+        ensure(&settings.buildah)?;
+    }
+
+    // Given a list of containers, this will map an operation over them (complete with a progress spinner.)
+    // 
+    // Up to `parallelism` containers are operated on at once, one wave at a time - so the order
+    // of containers that depend on each other is still respected.
+    let map_set = |set: &[Container], func: fn(&Container) -> Result<()>, op: &str| -> Result<_> {
+        use std::sync::atomic::{AtomicUsize, Ordering};
 
-    // Given a list of container names, this will
-    // retrieve them in well-typed form and map an operation over them (complete with a progress spinner.)
-    let map_set = |set: &ContainerSet, func: fn(&Container) -> Result<()>, op: &str| -> Result<_> {
         let style = format!(
             "{{spinner}} {op} {{msg:.green}}..."
         );
@@ -75,48 +109,67 @@ fn main() -> Result<()> {
         let bar = ProgressBar::new_spinner()
             .with_style(style);
 
+        if !settings.progress {
+            bar.set_draw_target(ProgressDrawTarget::hidden());
+        }
+
         bar.enable_steady_tick(
             std::time::Duration::from_millis(100)
         );
-    
-        match set.all {
-            false => {
-                for id in &set.containers {
-                    bar.set_message(
-                        id.to_owned()
-                    );
-                    existence_check(id)?;
-
-                    func(
-                        &Container::from_id(id)?
-                    )?;
-                }
-            },
-            true => {
-                for ctr in Container::enumerate()? {
-                    bar.set_message(
-                        ctr
-                            .annotation("box.name")
-                            .unwrap_or("?")
-                            .to_owned()
-                    );
-
-                    func(&ctr)?;
-                }
-            },
+
+        let waves = match settings.parallelism {
+            1 => vec![set],
+            _ => waves(set)?
         };
 
+        for wave in waves {
+            let next = AtomicUsize::new(0);
+
+            std::thread::scope(|s| {
+                let workers: Vec<_> = (0..settings.parallelism.min(wave.len()))
+                    .map(|_| s.spawn(|| -> Result<()> {
+                        while let Some(ctr) = wave.get(next.fetch_add(1, Ordering::Relaxed)) {
+                            bar.set_message(
+                                ctr
+                                    .annotation("box.name")
+                                    .unwrap_or("?")
+                                    .to_owned()
+                            );
+
+                            if let Err(err) = func(ctr) {
+                                // Stop the other workers from picking up anything new.
+                                next.store(wave.len(), Ordering::Relaxed);
+
+                                return Err(err)
+                            }
+                        }
+
+                        Ok(())
+                    }))
+                    .collect();
+
+                workers
+                    .into_iter()
+                    .try_for_each(|w| w.join().expect("Worker thread should not panic"))
+            })?;
+        }
+
         Ok(())
     };
 
     // Instantiates containers from a slice of images, with a progress spinner.
-    let instantiate = |set: &[Image], replace| -> Result<_> {
+    // Overrides are looked up by the name of each image.
+    let instantiate = |set: &[Image], replace, overrides: &dyn Fn(&str) -> Overrides| -> Result<_> {
         let style = ProgressStyle::with_template("{spinner} Creating {msg:.green}...")
             .unwrap();
 
         let bar = ProgressBar::new_spinner()
             .with_style(style);
 
+        if !settings.progress {
+            bar.set_draw_target(ProgressDrawTarget::hidden());
+        }
+
         bar.enable_steady_tick(
             std::time::Duration::from_millis(100)
         );
@@ -126,22 +179,81 @@ fn main() -> Result<()> {
                 continue;
             }
 
+            let name = image
+                .annotation("box.name")
+                .unwrap_or("?");
+
             bar.set_message(
-                image
-                    .annotation("box.name")
-                    .unwrap_or("?")
-                    .to_owned()
+                name.to_owned()
             );
 
-            image.instantiate(replace)?;
+            image.instantiate_ext(replace, &overrides(name), &[])?;
         }
 
         Ok(())
     };
 
     match args.command {
+        Completions { shell } => completions(shell)?,
         Containers  => list_containers()?,
         Definitions => list_definitions()?,
+        Inspect { name } => inspect(&name)?,
+        Kube { images, all, output } => {
+            let set: Vec<_> = match all {
+                false => {
+                    let enumerated = Image::enumerate()?;
+                    let mut out    = vec![];
+
+                    for id in &images {
+                        out.extend(
+                            resolve::all("image", id, &enumerated)?
+                                .into_iter()
+                                .cloned()
+                        )
+                    }
+
+                    out
+                },
+                true => Image::enumerate()?
+            };
+
+            let yaml = kube::render(&set)?;
+
+            match output {
+                Some(path) => std::fs::write(path, yaml)
+                    .context("Fault when writing Kubernetes manifests")?,
+                None => print!("{yaml}")
+            }
+        },
+        Quadlet { name, print } => {
+            let image = Image::from_id(&name)?;
+
+            match print {
+                true  => print!("{}", quadlet::render(&image, false)?),
+                false => {
+                    let path = quadlet::install(&image, false)?;
+
+                    eprintln!("Wrote Quadlet unit to {}", path.to_string_lossy())
+                }
+            }
+        },
+        Autostart { operation } => match operation {
+            cli::Autostart::Enable { name } => {
+                let image = Image::from_id(&name)?;
+                let path  = quadlet::install(&image, true)?;
+
+                eprintln!(
+                    "Container {name} will be started at login (unit: {})",
+                    path.to_string_lossy()
+                )
+            },
+            cli::Autostart::Disable { name } => {
+                if !quadlet::uninstall(&name)? {
+                    warn!("No Quadlet unit found for {name}");
+                    eprintln!("Container {name} was not set to start at login.")
+                }
+            }
+        },
         Directory   => {
             println!(
                 "{}",
@@ -150,90 +262,255 @@ fn main() -> Result<()> {
         },
 
         Create { name } => Definition::create(name)?,
-        Edit   { name } => Definition::edit(name)?,
-        Delete { name, yes } => Definition::delete(name, yes)?,
+        Debug { name, discard } => {
+            let name = definition_name(name)?;
 
-        Enter { name } => {
-            existence_check(&name)?;
+            if discard {
+                Failure::discard(&name)?;
 
-            let ctr = Container::from_id(&name)?;
+                eprintln!("Removed the kept working container of {name}.");
+            }
+            else {
+                let failure = Failure::load(&name)?;
+
+                eprintln!(
+                    "Opening a shell in the working container of {name}; the failed step was {}",
+                    failure.directive.join(" ")
+                );
+
+                std::process::exit(
+                    failure.shell()?
+                )
+            }
+        },
+        Edit   { name } => Definition::edit(definition_name(name)?)?,
+        Delete { name, yes } => {
+            let name = match name {
+                Some(name) if name != "-" => resolve::exact("definition", &name, &Definition::names()?)?.clone(),
+                name => definition_name(name)?
+            };
+
+            Definition::delete(name, yes)?
+        },
+
+        Enter { name, options, shell, login } => {
+            let ctr = container(name)?;
+
+            if !ctr.started() {
+                ctr.start()?;
+            }
+
+            std::process::exit(
+                ctr.enter(&options, shell.as_deref(), login)?
+            )
+        },
+        Exec { mut name, options, detach, mut command } => {
+            // With only one of the two, it's the program - and the container is chosen interactively.
+            if command.is_empty() {
+                match name.take() {
+                    Some(program) if std::io::stdout().is_terminal() => command.push(program),
+                    _ => bail!("No program to execute was provided")
+                }
+            }
+
+            let ctr = container(name)?;
 
             if !ctr.started() {
                 ctr.start()?;
             }
 
-            ctr.enter()?;
+            std::process::exit(
+                ctr.exec(&options, detach, &command)?
+            )
         },
-        Exec { name, path, args } => {
-            existence_check(&name)?;
+        ExportBin { name, command, rename } => {
+            let ctr = Container::from_id(&name)?;
+
+            if !ctr.started() {
+                ctr.start()?;
+            }
+
+            let path = export::bin(&ctr, &command, rename.as_deref())?;
 
+            eprintln!("Exported {command} to {}", path.to_string_lossy())
+        },
+        ExportApp { name, entry } => {
             let ctr = Container::from_id(&name)?;
 
             if !ctr.started() {
                 ctr.start()?;
             }
 
-            ctr.exec(&path, &args)?;
+            let path = export::app(&ctr, &entry)?;
+
+            eprintln!("Exported {entry} to {}", path.to_string_lossy())
         },
-        Ephemeral { name, path, mut args } => {
+        Ephemeral { name, overrides, command } => {
             let image = Image::from_id(&name)?;
 
             if let Some("false") = image.annotation("box.instantiate") {
                 bail!("Image {name} is defined as non-instantiable.")
             }
 
-            args.insert(0, path);
+            std::process::exit(
+                image.instantiate_ext(false, &overrides, &command)?
+            )
+        },
+
+        Build { mut defs, all, force, no_cache, keep_failed } => {
+            if defs.is_empty() && !all && std::io::stdout().is_terminal() {
+                defs.push(
+                    definition_name(None)?
+                );
+            }
+
+            if let Err(err) = build_set(&defs, all, force, no_cache, keep_failed) {
+                // Like a shell, report termination by a signal as 128 plus the signal number.
+                if let Some(Cancelled(signal)) = err.downcast_ref() {
+                    std::process::exit(128 + signal)
+                }
 
-            image.instantiate_ext(
-                false,
-                &args
-            )?;
+                return Err(err)
+            }
         },
 
-        Build { defs, all, force } => build_set(&defs, all, force)?,
+        Start { set, wait } => {
+            let ctrs = resolve_containers(&set, false)?;
 
-        Start   (set) => map_set(&set, Container::start, "Starting")?,
-        Stop    (set) => map_set(&set, Container::stop, "Stopping")?,
-        Restart (set) => map_set(&set, Container::restart, "Restarting")?,
-        Down    (set) => map_set(&set, Container::down, "Removing")?,
-        Reup    (set) => {
-            map_set(&set, Container::down, "Removing")?;
+            map_set(&ctrs, Container::start, "Starting")?;
 
-            let set: Vec<_> = match set.all {
-                false => {
-                    let mut out = vec![];
+            if wait.wait {
+                wait_ready(&ctrs, wait.timeout)?;
+            }
+        },
+        Restart (set) => map_set(&resolve_containers(&set, false)?, Container::restart, "Restarting")?,
+        Stop    (set) => {
+            let mut ctrs = resolve_containers(&set, false)?;
+            ctrs.reverse();
 
-                    for id in set.containers {
-                        out.push(
-                            Image::from_id(&id)?
-                        )
+            map_set(&ctrs, Container::stop, "Stopping")?
+        },
+        Down { set, volumes } => {
+            let mut ctrs = resolve_containers(&set, true)?;
+            ctrs.reverse();
+
+            map_set(&ctrs, Container::down, "Removing")?;
+
+            prune_networks()?;
+
+            for name in ctrs.iter().filter_map(|c| c.annotation("box.name")) {
+                export::remove(name)?;
+            }
+
+            if volumes {
+                let owners: HashSet<_> = ctrs
+                    .iter()
+                    .filter_map(|c| c.annotation("box.name"))
+                    .collect();
+
+                let removed: HashSet<_> = ctrs
+                    .iter()
+                    .map(|c| c.id.as_str())
+                    .collect();
+
+                for volume in Volume::enumerate()? {
+                    if !volume.owner().is_some_and(|o| owners.contains(o)) {
+                        continue;
                     }
-                    
-                    out
-                },
-                true => Image::enumerate()?
-            };
 
-            instantiate(&set, true)?;
-        },
-        Up { containers, all, replace } => {
-            let set: Vec<_> = match all {
-                false => {
-                    let mut out = vec![];
+                    let users = volume.users()?;
 
-                    for id in containers {
-                        out.push(
-                            Image::from_id(&id)?
-                        )
+                    if let Some(user) = users.iter().find(|u| !removed.contains(u.as_str())) {
+                        warning(
+                            "volume",
+                            &volume.name,
+                            &format!("was kept, as it is still mounted by container {:.12}", user),
+                            None
+                        );
+
+                        continue;
                     }
-                    
-                    out
+
+                    volume.remove()?;
+                }
+            }
+        },
+        Reup { set, overrides, wait } => {
+            let mut ctrs = resolve_containers(&set, false)?;
+            ctrs.reverse();
+
+            let recorded: HashMap<_, _> = ctrs
+                .iter()
+                .filter_map(|c| Some((
+                    c.annotation("box.name")?.to_owned(),
+                    c.overrides()
+                )))
+                .collect();
+
+            map_set(&ctrs, Container::down, "Removing")?;
+
+            let images = match set.all {
+                false => {
+                    let names: Vec<_> = ctrs
+                        .iter()
+                        .rev()
+                        .filter_map(|c| c.annotation("box.name"))
+                        .map(str::to_owned)
+                        .collect();
+
+                    resolve_images(&names, false)?
                 },
-                true => Image::enumerate()?
+                true => resolve_images(&[], true)?
             };
-            
-            instantiate(&set, replace)?;
+
+            warn_orphaned_volumes(&images)?;
+
+            instantiate(&images, true, &|name| match overrides.is_empty() {
+                true  => recorded.get(name).cloned().unwrap_or_default(),
+                false => overrides.clone()
+            })?;
+
+            if wait.wait {
+                wait_ready(&instantiated(&images)?, wait.timeout)?;
+            }
+        },
+        Up { mut containers, all, replace, overrides, wait } => {
+            let picked = containers.is_empty() && !all && std::io::stdout().is_terminal();
+
+            if picked {
+                let name = pick::pick(
+                    pick::Kind::Images,
+                    image_rows()?
+                )?;
+
+                containers.push(name);
+            }
+
+            let images = resolve_images(&containers, all)?;
+
+            instantiate(&images, replace, &|_| overrides.clone())?;
+
+            // Only remember the pick once its container has actually been created.
+            if picked {
+                pick::remember(pick::Kind::Images, &containers[0])?;
+            }
+
+            if wait.wait {
+                wait_ready(&instantiated(&images)?, wait.timeout)?;
+            }
         }
+        Logs { set, follow, since } => logs(
+            &resolve_containers(&set, false)?,
+            follow,
+            since.as_deref()
+        )?,
+        Top { format } => top(format.unwrap_or(settings.format))?,
+        Settings => show_settings()?,
+        Groups  => list_groups()?,
+        Volumes => list_volumes()?,
+        Network { operation } => match operation {
+            cli::Network::Ls => list_networks()?
+        },
 
         Init { shell } => match &*shell {
             "fish"  => {
@@ -256,9 +533,7 @@ fn main() -> Result<()> {
     Ok(())
 }
 
-fn install_logging() {
-    env_logger::init();
-
+fn install_hooks() {
     color_eyre::config::HookBuilder::new()
         .panic_section("Well, this is embarassing. It appears Box has crashed!\nConsider reporting the bug at <https://github.com/Colonial-Dev/box>.")
         .display_env_section(false)
@@ -267,89 +542,847 @@ fn install_logging() {
         .expect("Could not install Eyre hooks!");
 }
 
-/// Checks if a container exists, returning a well-formed error (with fuzzy-matched suggestions) if not.
-fn existence_check(id: &str) -> Result<()> {
-    if Container::exists(id)? {
-        Ok(())
-    }
-    else {
-        let containers = Container::enumerate()
-            .context("Failed to enumerate containers for fuzzy matching")?;
+/// Resolves a set of container names (which may include `@group` selectors) into well-typed containers,
+/// ordered so that every container comes after those it depends on.
+/// 
+/// If `exact` is set, names must be exact (see [`resolve::exact`]) rather than prefixes or globs.
+fn resolve_containers(set: &ContainerSet, exact: bool) -> Result<Containers> {
+    let images = Image::enumerate()?;
+    let ctrs   = Container::enumerate()?;
+
+    let mut out = match set.all {
+        true  => ctrs,
+        false => {
+            let mut out = vec![];
+
+            for id in &set.containers {
+                match id.strip_prefix('@') {
+                    Some(group) => for name in group_members(group, &images)? {
+                        // Not every member of a group necessarily has a container.
+                        match ctrs.iter().find(|c| c.annotation("box.name") == Some(name)) {
+                            Some(ctr) => out.push(ctr.clone()),
+                            None => info!("Skipping group member {name} (no container)")
+                        }
+                    },
+                    None if exact => out.push(
+                        resolve::exact("container", id, &ctrs)?.clone()
+                    ),
+                    None => out.extend(
+                        resolve::all("container", id, &ctrs)?
+                            .into_iter()
+                            .cloned()
+                    )
+                }
+            }
+
+            out
+        }
+    };
+
+    // Groups may overlap with each other and with explicitly named containers.
+    let mut seen = HashSet::new();
+
+    out.retain(|c| seen.insert(c.id.clone()));
+
+    let order = startup_order(&images)?;
+
+    out.sort_by_key(|c| {
+        c
+            .annotation("box.name")
+            .and_then(|n| order.iter().position(|o| o == n))
+            .unwrap_or(usize::MAX)
+    });
+
+    Ok(out)
+}
+
+/// Resolves a list of image names (which may include `@group` selectors) into well-typed images,
+/// ordered so that every image comes after those it depends on.
+/// 
+/// If `all` is true, every image managed by Box is returned instead.
+fn resolve_images(ids: &[String], all: bool) -> Result<Images> {
+    let images = Image::enumerate()?;
+    let order  = startup_order(&images)?;
+
+    let mut out = match all {
+        true  => images,
+        false => {
+            let mut out = vec![];
+
+            for id in ids {
+                match id.strip_prefix('@') {
+                    Some(group) => for name in group_members(group, &images)? {
+                        out.push(
+                            resolve::one("image", name, &images)?.clone()
+                        )
+                    },
+                    None => out.extend(
+                        resolve::all("image", id, &images)?
+                            .into_iter()
+                            .cloned()
+                    )
+                }
+            }
+
+            out
+        }
+    };
+
+    let mut seen = HashSet::new();
+
+    out.retain(|i| seen.insert(i.id.clone()));
+
+    out.sort_by_key(|i| {
+        i
+            .annotation("box.name")
+            .and_then(|n| order.iter().position(|o| o == n))
+            .unwrap_or(usize::MAX)
+    });
+
+    Ok(out)
+}
+
+/// Returns the names of every image in the provided group, erroring if there are none.
+fn group_members<'a>(group: &str, images: &'a [Image]) -> Result<Vec<&'a str>> {
+    if group.is_empty() {
+        bail!("Group selector '@' is missing a group name")
+    }
+
+    let members: Vec<_> = images
+        .iter()
+        .filter(|i| i.annotation("box.group") == Some(group))
+        .filter_map(|i| i.annotation("box.name"))
+        .collect();
+
+    if members.is_empty() {
+        let err = eyre!("Tried to operate on a group ({group}) with no members")
+            .suggestion("Use 'bx groups' to list all known groups.")
+            .suggestion("Groups are assigned with the 'group' metadata key - have you rebuilt since adding it?");
+
+        return Err(err)
+    }
+
+    Ok(members)
+}
+
+/// Topologically sorts the names of the provided images by their `depends_on` metadata,
+/// such that every name comes after those it depends on.
+fn startup_order(images: &[Image]) -> Result<Vec<String>> {
+    use petgraph::Graph;
+    use petgraph::algo::toposort;
+
+    let mut indices = HashMap::new();
+    let mut graph   = Graph::<&str, ()>::new();
+
+    for name in images.iter().filter_map(|i| i.annotation("box.name")) {
+        indices.insert(
+            name,
+            graph.add_node(name)
+        );
+    }
+
+    for image in images {
+        let Some(name) = image.annotation("box.name") else {
+            continue
+        };
+
+        for dep in image.annotation_values("box.depends_on") {
+            // Dependencies without a built image can't affect ordering.
+            if let Some(dep) = indices.get(dep) {
+                graph.update_edge(
+                    *dep,
+                    indices[name],
+                    ()
+                );
+            }
+        }
+    }
+
+    let topo = toposort(&graph, None)
+        .map_err(|e| eyre!{"{e:?}"})
+        .context("Cycle detected in image dependency graph")?;
+
+    let order = topo
+        .into_iter()
+        .map(|idx| graph[idx].to_owned())
+        .collect();
+
+    Ok(order)
+}
+
+/// Splits an ordered set of containers into consecutive waves that can each be operated on at once.
+/// 
+/// A new wave is started whenever a container depends on (or is depended on by) one already in the
+/// current wave, so the order of the set is preserved wherever it matters - whichever way it runs.
+fn waves(set: &[Container]) -> Result<Vec<&[Container]>> {
+    let images = Image::enumerate()?;
+
+    let depends: HashMap<_, Vec<_>> = images
+        .iter()
+        .filter_map(|i| Some((i.annotation("box.name")?, i.annotation_values("box.depends_on"))))
+        .collect();
+
+    // Everything each box depends on, directly or otherwise.
+    let ancestors = |name: &str| {
+        let mut seen  = HashSet::new();
+        let mut stack = vec![name];
+
+        while let Some(n) = stack.pop() {
+            for dep in depends.get(n).into_iter().flatten() {
+                if seen.insert(*dep) {
+                    stack.push(dep);
+                }
+            }
+        }
+
+        seen
+    };
+
+    let ancestors: HashMap<_, _> = set
+        .iter()
+        .filter_map(|c| c.annotation("box.name"))
+        .map(|n| (n, ancestors(n)))
+        .collect();
+
+    let related = |a: &Container, b: &Container| match (a.annotation("box.name"), b.annotation("box.name")) {
+        (Some(a), Some(b)) => ancestors[a].contains(b) || ancestors[b].contains(a),
+        _ => false
+    };
+
+    let mut waves = vec![];
+    let mut start = 0;
+
+    for (i, ctr) in set.iter().enumerate() {
+        if set[start..i].iter().any(|c| related(c, ctr)) {
+            waves.push(&set[start..i]);
+            start = i;
+        }
+    }
+
+    if start < set.len() {
+        waves.push(&set[start..]);
+    }
+
+    Ok(waves)
+}
+
+/// Prints the output of the provided containers.
+/// 
+/// The output of multiple containers is interleaved line by line, with each line prefixed by the (colored) name
+/// of the container it came from.
+fn logs(ctrs: &[Container], follow: bool, since: Option<&str>) -> Result<()> {
+    use std::io::{BufRead, BufReader, Read};
+    use std::process::Stdio;
+    use std::thread;
+
+    use colored::{Color, Colorize};
+
+    if let [ctr] = ctrs {
+        return ctr
+            .logs(follow, since)
+            .spawn_ok()
+            .context("Fault when fetching container logs")
+    }
+
+    const COLORS: [Color; 6] = [
+        Color::Green,
+        Color::Cyan,
+        Color::Magenta,
+        Color::Yellow,
+        Color::Blue,
+        Color::Red
+    ];
+
+    let width = ctrs
+        .iter()
+        .filter_map(|c| c.annotation("box.name"))
+        .map(str::len)
+        .max()
+        .unwrap_or_default();
+
+    let mut children = vec![];
+    let mut threads  = vec![];
+
+    for (i, ctr) in ctrs.iter().enumerate() {
+        let name = ctr
+            .annotation("box.name")
+            .unwrap_or("?");
+
+        let prefix = format!("{name:<width$} |")
+            .color(COLORS[i % COLORS.len()])
+            .bold()
+            .to_string();
+
+        let mut child = ctr
+            .logs(follow, since)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("Fault when fetching container logs")?;
+
+        let stdout = child.stdout.take().expect("Standard output should be piped");
+        let stderr = child.stderr.take().expect("Standard error should be piped");
+
+        let streams: [(Box<dyn Read + Send>, bool); 2] = [
+            (Box::new(stdout), false),
+            (Box::new(stderr), true)
+        ];
+
+        for (stream, is_stderr) in streams {
+            let prefix = prefix.clone();
+
+            threads.push(thread::spawn(move || {
+                for line in BufReader::new(stream).lines().map_while(Result::ok) {
+                    match is_stderr {
+                        false => println!("{prefix} {line}"),
+                        true  => eprintln!("{prefix} {line}")
+                    }
+                }
+            }));
+        }
+
+        children.push((name, child));
+    }
+
+    for thread in threads {
+        let _ = thread.join();
+    }
+
+    let mut failed = vec![];
+
+    for (name, mut child) in children {
+        let status = child
+            .wait()
+            .context("Fault when fetching container logs")?;
+
+        if !status.success() {
+            failed.push(format!("{name} ({status})"));
+        }
+    }
+
+    if !failed.is_empty() {
+        let err = eyre!("Fault when fetching container logs")
+            .section(failed.join("\n").header("Failed:"))
+            .suggestion("Check that the containers still exist, or run bx logs on each of them for details.");
+
+        return Err(err)
+    }
+
+    Ok(())
+}
+
+/// Fetches the containers that were created from the provided images.
+fn instantiated(images: &[Image]) -> Result<Containers> {
+    let ctrs = Container::enumerate()?;
+
+    let mut out = vec![];
+
+    for image in images {
+        if let Some("false") = image.annotation("box.instantiate") {
+            continue;
+        }
+
+        let name = image
+            .annotation("box.name")
+            .expect("Name annotation should be set");
+
+        out.push(
+            resolve::one("container", name, &ctrs)?.clone()
+        );
+    }
+
+    Ok(out)
+}
+
+/// Waits for all the provided containers to become healthy (or merely running, if they
+/// have no health check), with a progress spinner for each.
+fn wait_ready(ctrs: &[Container], timeout: u64) -> Result<()> {
+    use std::time::{Duration, Instant};
+    use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+
+    let style = ProgressStyle::with_template("{spinner} Waiting for {msg:.green}...")
+        .unwrap();
+
+    let done = ProgressStyle::with_template("  {msg}")
+        .unwrap();
+
+    let bars = match settings().progress {
+        true  => MultiProgress::new(),
+        false => MultiProgress::with_draw_target(ProgressDrawTarget::hidden())
+    };
+
+    let mut pending: Vec<_> = ctrs
+        .iter()
+        .map(|c| {
+            let name = c
+                .annotation("box.name")
+                .unwrap_or("?")
+                .to_owned();
+
+            let bar = bars.add(
+                ProgressBar::new_spinner()
+                    .with_style(style.clone())
+                    .with_message(name.clone())
+            );
+
+            bar.enable_steady_tick(
+                Duration::from_millis(100)
+            );
+
+            (c.id.clone(), name, bar)
+        })
+        .collect();
+
+    let start   = Instant::now();
+    let timeout = Duration::from_secs(timeout);
+
+    while !pending.is_empty() {
+        let mut still_pending = vec![];
+
+        for (id, name, bar) in pending {
+            let ctr = Container::inspect(&id)?;
+
+            let ready = match &ctr.health {
+                Some(health) => match health.status.as_str() {
+                    "healthy"   => true,
+                    "unhealthy" => {
+                        bar.finish_and_clear();
+
+                        let log = health
+                            .log
+                            .iter()
+                            .flatten()
+                            .last()
+                            .map(|l| format!("(exit code {}) {}", l.exit_code, l.output.trim()))
+                            .unwrap_or_default()
+                            .header("Last health check:");
+
+                        let err = eyre!("Container {name} became unhealthy")
+                            .section(log)
+                            .suggestion(format!("Try 'podman logs {name}' for more information."));
+
+                        return Err(err)
+                    },
+                    _ => false
+                },
+                None => ctr.started()
+            };
+
+            if ctr.exited() {
+                bar.finish_and_clear();
+
+                let err = eyre!("Container {name} exited while waiting for it to become ready")
+                    .suggestion(format!("Try 'podman logs {name}' for more information."));
+
+                return Err(err)
+            }
+
+            if ready {
+                bar.set_style(done.clone());
+                bar.finish_with_message(format!("{name} is ready"));
+            }
+            else {
+                still_pending.push((id, name, bar));
+            }
+        }
+
+        pending = still_pending;
+
+        if pending.is_empty() {
+            break;
+        }
+
+        if start.elapsed() > timeout {
+            let names: Vec<_> = pending
+                .iter()
+                .map(|(_, name, bar)| {
+                    bar.finish_and_clear();
+                    name.as_str()
+                })
+                .collect();
+
+            let err = eyre!("Timed out waiting for container(s) to become ready: {}", names.join(", "))
+                .suggestion("Pass a longer --timeout if the container(s) are slow to start.");
+
+            return Err(err)
+        }
+
+        std::thread::sleep(
+            Duration::from_millis(500)
+        );
+    }
+
+    Ok(())
+}
+
+/// Resolves an optional container name into a container, letting the user choose one if it's missing.
+/// 
+/// `-` refers to the most recently used container.
+fn container(name: Option<String>) -> Result<Container> {
+    use pick::Kind::Containers;
+
+    let ctr = match name.as_deref() {
+        Some("-")  => Container::from_id(&pick::previous(Containers)?)?,
+        Some(name) => Container::from_id(name)?,
+        None => {
+            let ctrs         = Container::enumerate()?;
+            let descriptions = descriptions();
+
+            let rows = ctrs
+                .iter()
+                .filter_map(|c| {
+                    let name = c.annotation("box.name")?.to_owned();
+                    let desc = descriptions.get(&name).cloned().unwrap_or_default();
+
+                    Some(vec![name, c.state.clone(), desc])
+                })
+                .collect();
+
+            let name = pick::pick(Containers, rows)?;
+
+            resolve::one("container", &name, &ctrs)?.clone()
+        }
+    };
+
+    pick::remember(Containers, ctr.annotation("box.name").unwrap_or(&ctr.id))?;
+
+    Ok(ctr)
+}
+
+/// Resolves an optional definition name, letting the user choose one if it's missing.
+/// 
+/// `-` refers to the most recently used definition.
+fn definition_name(name: Option<String>) -> Result<String> {
+    use pick::Kind::Definitions;
+
+    let name = match name.as_deref() {
+        Some("-")  => pick::previous(Definitions)?,
+        Some(name) => resolve::one("definition", name, &Definition::names()?)?.clone(),
+        None => {
+            let built: HashSet<_> = Image::enumerate()?
+                .iter()
+                .filter_map(|i| i.annotation("box.name"))
+                .map(str::to_owned)
+                .collect();
+
+            let rows = Definition::enumerate()?
+                .into_iter()
+                .map(|d| {
+                    let name  = d.name().to_owned();
+                    let state = match built.contains(&name) {
+                        true  => "built",
+                        false => "not built"
+                    };
+
+                    vec![name, state.to_owned(), d.meta.description.unwrap_or_default()]
+                })
+                .collect();
+
+            pick::pick(Definitions, rows)?
+        }
+    };
+
+    pick::remember(Definitions, &name)?;
+
+    Ok(name)
+}
+
+/// Rows describing every instantiable image, for choosing one interactively.
+fn image_rows() -> Result<Vec<Vec<String>>> {
+    let descriptions = descriptions();
+
+    let states: HashMap<_, _> = Container::enumerate()?
+        .into_iter()
+        .filter_map(|c| Some((c.annotation("box.name")?.to_owned(), c.state)))
+        .collect();
+
+    let rows = Image::enumerate()?
+        .iter()
+        .filter(|i| i.annotation("box.instantiate") != Some("false"))
+        .filter_map(|i| {
+            let name  = i.annotation("box.name")?.to_owned();
+            let state = states.get(&name).cloned().unwrap_or("not created".to_owned());
+            let desc  = descriptions.get(&name).cloned().unwrap_or_default();
+
+            Some(vec![name, state, desc])
+        })
+        .collect();
+
+    Ok(rows)
+}
+
+/// Maps the names of definitions to their descriptions (if they have one.)
+fn descriptions() -> HashMap<String, String> {
+    Definition::enumerate()
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|d| Some((d.name().to_owned(), d.meta.description?)))
+        .collect()
+}
+
+/// Determines the directory Box keeps state (such as recently used names) in, creating it if necessary.
+/// 
+/// - `$XDG_STATE_HOME/box`
+/// - `$HOME/.local/state/box`
+fn state_directory() -> Result<std::path::PathBuf> {
+    let dir = xdg_home("XDG_STATE_HOME", ".local/state")
+        .context("Could not find a valid directory for Box's state")?
+        .join("box");
+
+    if !dir.exists() {
+        std::fs::create_dir_all(&dir)
+            .context("Failed to create state directory")?;
+    }
+
+    Ok(dir)
+}
+
+/// Checks that a program exists on the system's PATH, returning a well-formed error if not.
+fn ensure(program: &str) -> Result<()> {
+    use std::io::ErrorKind;
+    use std::process::Command;
+
+    match Command::new(program)
+        .arg("--version")
+        .output()
+    {
+        Ok(out) => {
+            info!(
+                "Ensured {}",
+                String::from_utf8_lossy(&out.stdout).trim()
+            );
+
+            Ok(())
+        },
+        Err(e) => {
+            let err = if let ErrorKind::NotFound = e.kind() {
+                eyre!("{program} not found in PATH")
+                    .note("Box needs this program to run.")
+                    .suggestion("Make sure it's installed and in your PATH.")
+            } else {
+                eyre!(e)
+                    .wrap_err("Unable to determine if {program} exists")
+                    .note("Box needs this program to run.")
+                    .suggestion("Maybe you have permission issues?")
+            };
+
+            Err(err)
+        }
+    }
+}
+
+fn list_containers() -> Result<()> {
+    use comfy_table::Table;
+    use comfy_table::presets::NOTHING;
+
+    let mut table = Table::new();
+    let ctrs      = Container::enumerate()?;
+
+    let rows = ctrs
+        .iter()
+        .map(|c| [
+            c.annotation("box.name").unwrap(),
+            c.image.as_str(),
+            c.state.as_str()
+        ]);
+
+    table
+        .load_preset(NOTHING)
+        .set_header(["Name", "Image", "Status"])
+        .add_rows(rows);
+
+    println!("{table}");
+
+    Ok(())
+}
+
+/// Prints a script that registers `bx` with the shell's completion system.
+/// 
+/// Completions are generated by calling back into `bx` with `COMPLETE` set,
+/// so container and definition names are always up to date.
+fn completions(shell: cli::Shell) -> Result<()> {
+    use clap_complete::env::{self, EnvCompleter};
+
+    let completer: &dyn EnvCompleter = match shell {
+        cli::Shell::Bash => &env::Bash,
+        cli::Shell::Zsh  => &env::Zsh,
+        cli::Shell::Fish => &env::Fish,
+    };
+
+    completer
+        .write_registration("COMPLETE", "bx", "bx", "bx", &mut std::io::stdout())
+        .context("Fault when writing completion script")?;
+
+    Ok(())
+}
+
+/// Prints the current settings, after they've been loaded and validated.
+fn show_settings() -> Result<()> {
+    let path = settings::settings_path()?;
+
+    match path.exists() {
+        true  => eprintln!("Settings in {} are valid.", path.to_string_lossy()),
+        false => eprintln!("No settings file found at {}; using the defaults.", path.to_string_lossy())
+    }
+
+    // Show where definitions actually live, even if the setting isn't given.
+    let mut shown = settings().clone();
+
+    shown.definitions = Some(build::definition_directory()?);
+
+    print!(
+        "{}",
+        toml::to_string(&shown).context("Fault when serializing settings")?
+    );
+
+    Ok(())
+}
+
+fn list_groups() -> Result<()> {
+    use std::collections::BTreeMap;
+
+    use comfy_table::Table;
+    use comfy_table::presets::NOTHING;
+
+    let images = Image::enumerate()?;
+    let ctrs   = Container::enumerate()?;
+    let order  = startup_order(&images)?;
 
-        let names = containers
+    let mut groups: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+
+    for name in &order {
+        let group = images
             .iter()
-            .filter_map(|c|
-                c.annotation("box.name") 
-            );
+            .find(|i| i.annotation("box.name") == Some(name))
+            .and_then(|i| i.annotation("box.group"));
+
+        if let Some(group) = group.filter(|g| !g.is_empty()) {
+            groups
+                .entry(group)
+                .or_default()
+                .push(name);
+        }
+    }
 
-        let mut fuzzy = Fuzzy::new();
+    let rows = groups
+        .iter()
+        .map(|(group, members)| {
+            let running = members
+                .iter()
+                .filter(|m| {
+                    ctrs
+                        .iter()
+                        .any(|c| c.annotation("box.name") == Some(m) && c.started())
+                })
+                .count();
+
+            [
+                format!("@{group}"),
+                members.join(", "),
+                format!("{running}/{}", members.len())
+            ]
+        });
 
-        for s in names { fuzzy.add(s); }
+    let mut table = Table::new();
 
-        let suggestion = match fuzzy.find(id).first() {
-            Some(m) => format!("Did you mean '{}'?", m.1),
-            None => "Did you make a typo?".to_string(),
-        };
+    table
+        .load_preset(NOTHING)
+        .set_header(["Group", "Members", "Running"])
+        .add_rows(rows);
 
-        let err = eyre!("Tried to operate on a container ({id}) that does not exist")
-            .suggestion(suggestion);
+    println!("{table}");
 
-        Err(err)
-    }
+    Ok(())
 }
 
-/// Checks that a program exists on the system's PATH, returning a well-formed error if not.
-fn ensure(program: &str) -> Result<()> {
-    use std::io::ErrorKind;
-    use std::process::Command;
+fn list_networks() -> Result<()> {
+    use std::collections::BTreeMap;
 
-    match Command::new(program)
-        .arg("--version")
-        .output()
-    {
-        Ok(out) => {
-            info!(
-                "Ensured {}",
-                String::from_utf8_lossy(&out.stdout).trim()
-            );
+    use comfy_table::Table;
+    use comfy_table::presets::NOTHING;
 
-            Ok(())
-        },
-        Err(e) => {
-            let err = if let ErrorKind::NotFound = e.kind() {
-                eyre!("{program} not found in PATH")
-                    .note("Box needs this program to run.")
-                    .suggestion("Make sure it's installed and in your PATH.")
-            } else {
-                eyre!(e)
-                    .wrap_err("Unable to determine if {program} exists")
-                    .note("Box needs this program to run.")
-                    .suggestion("Maybe you have permission issues?")
-            };
+    let ctrs    = Container::enumerate()?;
+    let managed = managed_networks()?;
 
-            Err(err)
+    let mut networks: BTreeMap<&str, Vec<&str>> = managed
+        .iter()
+        .map(|n| (n.as_str(), vec![]))
+        .collect();
+
+    for ctr in &ctrs {
+        for network in &ctr.networks {
+            networks
+                .entry(network)
+                .or_default()
+                .push(
+                    ctr.annotation("box.name").unwrap_or("?")
+                );
         }
     }
+
+    let rows = networks
+        .iter()
+        .map(|(network, boxes)| [
+            network.to_string(),
+            match managed.iter().any(|m| m == network) {
+                true  => "yes",
+                false => "no"
+            }.to_owned(),
+            boxes.join(", ")
+        ]);
+
+    let mut table = Table::new();
+
+    table
+        .load_preset(NOTHING)
+        .set_header(["Network", "Managed", "Boxes"])
+        .add_rows(rows);
+
+    println!("{table}");
+
+    Ok(())
 }
 
-fn list_containers() -> Result<()> {
+fn list_volumes() -> Result<()> {
     use comfy_table::Table;
     use comfy_table::presets::NOTHING;
 
     let mut table = Table::new();
-    let ctrs      = Container::enumerate()?;
+    let volumes   = Volume::enumerate()?;
+    let images    = Image::enumerate()?;
 
-    let rows = ctrs
+    let mut mounted = HashMap::new();
+
+    for image in &images {
+        if let Some(name) = image.annotation("box.name") {
+            let local = local::Local::load(name)?;
+
+            mounted
+                .entry(name)
+                .or_insert_with(Vec::new)
+                .extend(image.volumes(&local));
+        }
+    }
+
+    let rows = volumes
         .iter()
-        .map(|c| [
-            c.annotation("box.name").unwrap(),
-            c.image.as_str(),
-            c.state.as_str()
-        ]);
+        .map(|v| {
+            let owner = v.owner().unwrap_or("?");
+
+            let used = mounted
+                .get(owner)
+                .is_some_and(|m| m.contains(&v.name));
+
+            [
+                v.name.as_str(),
+                owner,
+                if used { "yes" } else { "no (orphaned)" }
+            ]
+        });
 
     table
         .load_preset(NOTHING)
-        .set_header(["Name", "Image", "Status"])
+        .set_header(["Volume", "Box", "In Use"])
         .add_rows(rows);
 
     println!("{table}");
@@ -357,6 +1390,186 @@ fn list_containers() -> Result<()> {
     Ok(())
 }
 
+/// Warns about volumes owned by the provided images' boxes that their definitions no longer mount
+/// (e.g. because the volume was renamed.)
+fn warn_orphaned_volumes(images: &[Image]) -> Result<()> {
+    let volumes = Volume::enumerate()?;
+
+    for image in images {
+        let Some(name) = image.annotation("box.name") else {
+            continue
+        };
+
+        let mounted = image.volumes(
+            &local::Local::load(name)?
+        );
+
+        for volume in volumes.iter().filter(|v| v.owner() == Some(name)) {
+            if mounted.contains(&volume.name) {
+                continue;
+            }
+
+            warning(
+                "volume",
+                &volume.name,
+                &format!("is no longer used by {name}"),
+                Some(&format!("Remove it with 'podman volume rm {}' if it is no longer needed.", volume.name))
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Print a warning about the named object, with an optional hint on what to do about it.
+/// 
+/// Unlike `warn!`, these are always shown, so should be reserved for things the user needs to know about.
+pub fn warning(kind: &str, name: &str, message: &str, hint: Option<&str>) {
+    use colored::Colorize;
+
+    eprintln!(
+        "{}{} {} {}",
+        "Warning".bold().yellow(),
+        format!(": {kind}").bold().bright_white(),
+        name.bold().green(),
+        message.bold().bright_white()
+    );
+
+    if let Some(hint) = hint {
+        eprintln!("  {hint}");
+    }
+}
+
+fn top(format: Format) -> Result<()> {
+    use comfy_table::Table;
+    use comfy_table::presets::NOTHING;
+    use serde_json::json;
+
+    // Limits only change when images are rebuilt, so there's no need to refresh these.
+    let mut limits = HashMap::new();
+
+    for image in Image::enumerate()? {
+        let Some(name) = image.annotation("box.name") else {
+            continue
+        };
+
+        let local = local::Local::load(name)?;
+
+        if let Some(limit) = image.configured_values("memory", &local).pop() {
+            limits.insert(name.to_owned(), limit.to_owned());
+        }
+    }
+
+    loop {
+        let ctrs = Container::enumerate()?;
+
+        let running: Vec<_> = ctrs
+            .iter()
+            .filter(|c| c.started())
+            .map(|c| c.id.as_str())
+            .collect();
+
+        // ID -> [CPU, memory, PIDs, network I/O]
+        let mut stats = HashMap::new();
+
+        if !running.is_empty() {
+            let o = podman()
+                .args([
+                    "stats",
+                    "--no-stream",
+                    "--format",
+                    "{{.ID}}\t{{.CPUPerc}}\t{{.MemUsage}}\t{{.PIDs}}\t{{.NetIO}}"
+                ])
+                .args(&running)
+                .output_ok()
+                .context("Failed to fetch container statistics")?;
+
+            for line in o.lines() {
+                let mut fields = line.split('\t');
+
+                let Some(id) = fields.next() else {
+                    continue
+                };
+
+                stats.insert(
+                    id.to_owned(),
+                    fields.map(str::to_owned).collect::<Vec<_>>()
+                );
+            }
+        }
+
+        let rows: Vec<_> = ctrs
+            .iter()
+            .map(|c| {
+                let name = c.annotation("box.name").unwrap_or("?");
+
+                let limit = limits
+                    .get(name)
+                    .map(String::as_str)
+                    .unwrap_or("none");
+
+                // Podman truncates IDs in its output.
+                let stats = stats
+                    .iter()
+                    .find(|(id, _)| c.id.starts_with(id.as_str()))
+                    .map(|(_, s)| s.as_slice())
+                    .unwrap_or_default();
+
+                let field = |i: usize| stats
+                    .get(i)
+                    .map(String::as_str)
+                    .unwrap_or("-");
+
+                [
+                    name.to_owned(),
+                    c.state.clone(),
+                    field(0).to_owned(),
+                    // Podman reports usage as "used / limit" - we're only interested in the former,
+                    // as the limit is reported separately.
+                    field(1).split(" / ").next().unwrap_or("-").to_owned(),
+                    limit.to_owned(),
+                    field(2).to_owned(),
+                    field(3).to_owned(),
+                ]
+            })
+            .collect();
+
+        if let Format::Json = format {
+            let rows: Vec<_> = rows
+                .iter()
+                .map(|[name, state, cpu, memory, limit, pids, net]| json!({
+                    "name"         : name,
+                    "state"        : state,
+                    "cpu"          : cpu,
+                    "memory"       : memory,
+                    "memory_limit" : limit,
+                    "pids"         : pids,
+                    "net_io"       : net,
+                }))
+                .collect();
+
+            println!("{}", serde_json::to_string_pretty(&rows)?);
+
+            return Ok(())
+        }
+
+        let mut table = Table::new();
+
+        table
+            .load_preset(NOTHING)
+            .set_header(["Name", "Status", "CPU", "Memory", "Limit", "PIDs", "Net I/O"])
+            .add_rows(rows);
+
+        // Clear the screen and move the cursor to the top-left corner.
+        print!("\x1B[2J\x1B[H");
+        println!("{table}");
+
+        std::thread::sleep(
+            std::time::Duration::from_secs(2)
+        );
+    }
+}
+
 fn list_definitions() -> Result<()> {
     use comfy_table::Table;
     use comfy_table::presets::NOTHING;
@@ -386,8 +1599,184 @@ fn list_definitions() -> Result<()> {
     Ok(())
 }
 
+fn inspect(name: &str) -> Result<()> {
+    use colored::Colorize;
+
+    let image = Image::from_id(name)
+        .context("Fault when fetching image to inspect")
+        .suggestion("Has the definition been built yet?")?;
+
+    let heading = |s: &str| println!("{}", s.bold().bright_white());
+
+    let field = |key: &str, value: &str| println!(
+        "  {:<12} {value}",
+        key.green()
+    );
+
+    let unset = || "(none)".dimmed().to_string();
+
+    heading("Image");
+
+    field("Name", image.annotation("box.name").unwrap_or("?"));
+    field("ID", &image.id);
+    field("Definition", image.annotation("box.path").unwrap_or("?"));
+    field("Hash", image.annotation("box.hash").unwrap_or("?"));
+    field("Tree hash", image.annotation("box.tree").unwrap_or("?"));
+
+    let depends_on = image.annotation_values("box.depends_on");
+
+    match depends_on.is_empty() {
+        false => field("Depends on", &depends_on.join(", ")),
+        true  => field("Depends on", &unset())
+    }
+
+    heading("Configuration");
+
+    let mut configured = false;
+
+    for a in ANNOTATIONS {
+        let mut values: Vec<_> = image
+            .annotation_values(&a.key())
+            .into_iter()
+            .map(str::to_owned)
+            .collect();
+
+        // Options the image doesn't set fall back to the defaults in the settings file.
+        if values.is_empty() {
+            values = settings()
+                .default_values(a.name)
+                .into_iter()
+                .map(|v| format!("{v} {}", "(default)".dimmed()))
+                .collect();
+        }
+
+        for (i, v) in values.iter().enumerate() {
+            field(if i == 0 { a.name } else { "" }, v);
+            configured = true;
+        }
+    }
+
+    if !configured {
+        println!("  {}", unset());
+    }
+
+    heading("Presets");
+
+    let presets = image.annotation_values("box.presets");
+
+    if presets.is_empty() {
+        println!("  {}", unset());
+    }
+
+    for preset in presets {
+        let contributions = image.annotation_values(
+            &format!("box.preset.{preset}")
+        );
+
+        if contributions.is_empty() {
+            field(preset, &"(no runtime configuration)".dimmed().to_string());
+        }
+
+        for (i, c) in contributions.iter().enumerate() {
+            let c = c.replacen('=', " ", 1);
+
+            field(if i == 0 { preset } else { "" }, &c);
+        }
+    }
+
+    heading("Local overrides");
+
+    let local = local::Local::load(
+        image.annotation("box.name").unwrap_or(name)
+    )?;
+
+    let tables = [
+        ('=', &local.replace),
+        ('-', &local.remove),
+        ('+', &local.add),
+    ];
+
+    let mut overridden = false;
+
+    for (op, options) in tables {
+        for (option, values) in options {
+            for v in values.as_slice() {
+                field(option, &format!("{op} {v}"));
+                overridden = true;
+            }
+        }
+    }
+
+    if !overridden {
+        println!("  {}", unset());
+    }
+
+    heading("Command line");
+
+    println!(
+        "  podman {}",
+        shell_join(
+            &image.command_line(false, &Overrides::default(), &[])?
+        )
+    );
+
+    heading("Container");
+
+    let name = image
+        .annotation("box.name")
+        .expect("Name annotation should be set");
+
+    if !Container::exists(name)? {
+        println!("  {}", unset());
+        return Ok(())
+    }
+
+    let ctr = Container::inspect(name)?;
+
+    field("ID", &ctr.id);
+    field("Image", &ctr.image);
+    field("State", &ctr.state);
+
+    let hash = ctr.annotation("box.hash").unwrap_or("?");
+
+    if Some(hash) == image.annotation("box.hash") {
+        field("Hash", hash);
+    }
+    else {
+        field(
+            "Hash",
+            &format!("{hash} {}", "(out of date - consider reup)".yellow())
+        );
+    }
+
+    if !ctr.command.is_empty() {
+        field("Created with", &shell_join(&ctr.command));
+    }
+
+    Ok(())
+}
+
+/// Joins a list of arguments into a single string, quoting them for the shell where necessary.
+fn shell_join(args: &[String]) -> String {
+    let quote = |s: &String| {
+        let safe = !s.is_empty() && s
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_=.,/:@%+".contains(c));
+
+        match safe {
+            true  => s.to_owned(),
+            false => format!("'{}'", s.replace('\'', "'\\''"))
+        }
+    };
+
+    args
+        .iter()
+        .map(quote)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
 fn evaluate_config(operation: String, args: Vec<String>) -> Result<()> {
-    use std::process::Command;
 
     let Ok(ctr) = std::env::var("__BOX_BUILD_CTR") else {
         let err = eyre!("Config command must be invoked inside of a build context")
@@ -419,33 +1808,54 @@ fn evaluate_config(operation: String, args: Vec<String>) -> Result<()> {
 
     debug!("Post-processed arguments: {args:?} // {trailing:?}");
 
+    // Everything that determines what a step does, for build checkpoints.
+    let directive = |op| -> Vec<&str> {
+        [op]
+            .into_iter()
+            .chain(args.iter().map(String::as_str))
+            .chain(["--"])
+            .chain(trailing.iter().map(String::as_str))
+            .collect()
+    };
+
     match operation.as_str() {
         // We handle ADD/COPY and RUN in Rust code,
         // because correctly handling arguments split by --
         // in shell is... non trivial.
         "run" => {
-            let mut c = Command::new("buildah");
+            let mut c = buildah();
 
             c.arg("run");
 
             if trailing.is_empty() {
                 c
-                    .arg(ctr)
+                    .arg(&ctr)
                     .arg("--")
                     .args(args);
             }
             else {
                 c
                     .args(args)
-                    .arg(ctr)
+                    .arg(&ctr)
                     .arg("--")
                     .args(trailing);
             }
 
-            c.spawn_ok()?
+            // Only options given before a '--' are for Buildah.
+            let options = match trailing.is_empty() {
+                true  => &[][..],
+                false => args
+            };
+
+            // Bind mounts from the host are inputs of the step, just like the sources of an ADD.
+            let sources = checkpoint::bind_sources(options);
+
+            checkpoint::step(&ctr, &directive("run"), &sources, || {
+                keep_failed(&ctr, directive("run"), options, c.spawn_ok())
+            })?
         }
         "add" => {
-            let mut c = Command::new("buildah");
+            let mut c = buildah();
 
             c.arg("add");
 
@@ -455,76 +1865,188 @@ fn evaluate_config(operation: String, args: Vec<String>) -> Result<()> {
             // - Inverse of above.
             if trailing.is_empty() {
                 c
-                    .arg(ctr)
+                    .arg(&ctr)
                     .args(args);
             }
             else {
                 c
                     .args(args)
-                    .arg(ctr)
+                    .arg(&ctr)
                     .args(trailing);
             }
 
-            c.spawn_ok()?
+            // Everything but the destination (and any flags) is a source on the host.
+            let sources: Vec<_> = match trailing.is_empty() {
+                true  => args,
+                false => trailing
+            }
+            .split_last()
+            .map_or(&[][..], |(_, s)| s)
+            .iter()
+            .map(String::as_str)
+            .filter(|s| !s.starts_with('-'))
+            .collect();
+
+            checkpoint::step(&ctr, &directive("add"), &sources, || {
+                keep_failed(&ctr, directive("add"), &[], c.spawn_ok())
+            })?
         },
         "commit" => {
-            let mut c = Command::new("buildah");
+            let mut c = buildah();
 
             c.arg("commit");
 
             if trailing.is_empty() {
                 c
-                    .arg(ctr)
+                    .arg(&ctr)
                     .args(args);
             }
             else {
                 c
                     .args(args)
-                    .arg(ctr)
+                    .arg(&ctr)
                     .args(trailing);
             }
 
-            c.spawn_ok()?
+            // Every stage of the build (not just the one being committed) may have checkpoints worth keeping.
+            let mut chains = checkpoint::chain(&ctr)?;
+
+            let stages = std::env::var("__BOX_BUILD_CONTAINERS")
+                .ok()
+                .and_then(|p| std::fs::read_to_string(p).ok())
+                .unwrap_or_default();
+
+            for stage in stages.lines().filter(|s| *s != ctr) {
+                // Stages that were never created (or have already been removed) have nothing to keep.
+                if let Ok(chain) = checkpoint::chain(stage) {
+                    chains.extend(chain);
+                }
+            }
+
+            c.spawn_ok()?;
+
+            // Checkpoints from earlier versions of the definition are no longer useful.
+            if let Ok(name) = std::env::var("__BOX_BUILD_NAME") {
+                let keep: Vec<_> = chains
+                    .iter()
+                    .map(String::as_str)
+                    .collect();
+
+                if let Err(err) = checkpoint::prune(&name, &keep) {
+                    warn!("Could not remove stale build checkpoints: {err}");
+                }
+            }
         },
         "preset" => {
             evaluate_preset(&ctr, args)?
         },
-        o if ANNOTATIONS.contains(&o) => {
+        o => {
+            let Some(option) = Annotation::find(o) else {
+                let mut fuzzy = Fuzzy::new();
+
+                for a in ANNOTATIONS { fuzzy.add(a.name); }
+
+                let err = eyre!("Unknown configuration option {operation}")
+                    .suggestion(fuzzy.suggestion(o));
+
+                return Err(err);
+            };
+
             if args.is_empty() {
                 bail!("Configuration value not specified")
             }
 
-            // Gracefully handle the case where multiple values are provided.
+            // Catch malformed values now, rather than when the container is created.
             for a in args {
-                push_annotation(
+                (option.validate)(a)
+                    .context(format!("Invalid value {a:?} for configuration option {o}"))
+                    .suggestion(option.help)?;
+            }
+
+            if option.repeat {
+                // Gracefully handle the case where multiple values are provided.
+                for a in args {
+                    push_annotation(
+                        &ctr,
+                        &option.key(),
+                        a
+                    )?;
+                }
+            }
+            else {
+                let [a] = args else {
+                    bail!("Configuration option {o} only accepts a single value")
+                };
+
+                // Later values replace earlier ones (including those inherited from a base image.)
+                write_annotation(
                     &ctr,
-                    &format!("box.{o}"),
-                    a
+                    &option.key(),
+                    vec![a]
                 )?;
             }
-        },
-        _ => {
-            let err = eyre!("Unknown configuration option {operation}")
-                .suggestion("Did you make a typo?");
-
-            return Err(err);
         }
     }
 
     Ok(())
 }
 
+/// If the build was started with `--keep-failed`, records a failed step so its working container
+/// can be debugged with `bx debug`.
+fn keep_failed(ctr: &str, directive: Vec<&str>, options: &[String], result: Result<()>) -> Result<()> {
+    let Err(err) = result else {
+        return Ok(())
+    };
+
+    let keep = std::env::var("__BOX_BUILD_KEEP_FAILED")
+        .is_ok_and(|v| !v.is_empty());
+
+    let Ok(name) = std::env::var("__BOX_BUILD_NAME") else {
+        return Err(err)
+    };
+
+    if !keep {
+        return Err(err)
+    }
+
+    let failure = Failure {
+        container : ctr.to_owned(),
+        directive : directive.into_iter().map(str::to_owned).collect(),
+        options   : options.to_vec(),
+    };
+
+    Failure::record(&name, failure)?;
+
+    Err(
+        err.suggestion(format!("The working container was kept; run 'bx debug {name}' to open a shell inside it."))
+    )
+}
+
 /// Evaluates a definition script "preset."
 fn evaluate_preset(ctr: &str, args: &[String]) -> Result<()> {
     use std::ffi::OsString;
-    use std::process::Command;
 
+    let Some(name) = args.first() else {
+        let err = eyre!("Preset not specified")
+            .suggestion("PRESET directives cannot stand on their own");
+
+        return Err(err)
+    };
+
+    // Every annotation a preset writes is also recorded under its own key,
+    // so the contributions of each preset can be told apart later (e.g. by `bx inspect`.)
     let push_annotation = |key: &str, data: &str| {
-        push_annotation(ctr, key, data)
+        push_annotation(ctr, key, data)?;
+
+        push_annotation(
+            ctr,
+            &format!("box.preset.{name}"),
+            &format!("{}={data}", key.trim_start_matches("box."))
+        )
     };
 
     let run = |cmd: &str| {
-        Command::new("buildah")
+        buildah()
             .arg("run")
             .arg(ctr)
             .arg("sh")
@@ -534,13 +2056,6 @@ fn evaluate_preset(ctr: &str, args: &[String]) -> Result<()> {
             .context("Fault when running command inside working container")
     };
 
-    let Some(name) = args.first() else {
-        let err = eyre!("Preset not specified")
-            .suggestion("PRESET directives cannot stand on their own");
-
-        return Err(err)
-    };
-
     match name.as_str() {
         "cp-user" => {
             let name = match args.get(1) {
@@ -580,7 +2095,7 @@ fn evaluate_preset(ctr: &str, args: &[String]) -> Result<()> {
                 &format!("type=bind,src={sock},dst={sock}")
             )?;
 
-            Command::new("buildah")
+            buildah()
                 .arg("config")
                 .arg("--env")
                 .arg(
@@ -609,6 +2124,8 @@ fn evaluate_preset(ctr: &str, args: &[String]) -> Result<()> {
         }
     }
 
+    podman::push_annotation(ctr, "box.presets", name)?;
+
     Ok(())
 }
 
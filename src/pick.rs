@@ -0,0 +1,149 @@
+//! Interactive selection of containers, images and definitions when a name is omitted,
+//! backed by a most-recently-used list.
+use std::io::IsTerminal;
+use std::path::PathBuf;
+
+use crate::prelude::*;
+
+/// How many names each most-recently-used list remembers.
+const REMEMBERED: usize = 32;
+
+/// The kinds of names Box remembers separately.
+#[derive(Debug, Clone, Copy)]
+pub enum Kind {
+    Containers,
+    Definitions,
+    Images,
+}
+
+impl Kind {
+    fn noun(self) -> &'static str {
+        match self {
+            Self::Containers  => "container",
+            Self::Definitions => "definition",
+            Self::Images      => "image",
+        }
+    }
+
+    fn path(self) -> Result<PathBuf> {
+        let file = match self {
+            Self::Containers  => "recent-containers",
+            Self::Definitions => "recent-definitions",
+            Self::Images      => "recent-images",
+        };
+
+        Ok(
+            crate::state_directory()?.join(file)
+        )
+    }
+}
+
+/// Get the most recently used names of the provided kind, most recent first.
+pub fn recent(kind: Kind) -> Vec<String> {
+    kind
+        .path()
+        .and_then(|p| Ok(std::fs::read_to_string(p)?))
+        .unwrap_or_default()
+        .lines()
+        .filter(|l| !l.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Record that a name was just used.
+pub fn remember(kind: Kind, name: &str) -> Result<()> {
+    let mut names = recent(kind);
+
+    names.retain(|n| n != name);
+    names.insert(0, name.to_owned());
+    names.truncate(REMEMBERED);
+
+    std::fs::write(kind.path()?, names.join("\n") + "\n")
+        .context("Fault when saving recently used names")?;
+
+    Ok(())
+}
+
+/// Get the most recently used name of the provided kind (i.e. what `-` refers to.)
+pub fn previous(kind: Kind) -> Result<String> {
+    recent(kind)
+        .into_iter()
+        .next()
+        .with_context(|| format!("No {} has been used yet", kind.noun()))
+        .suggestion("Provide a name instead of '-'.")
+}
+
+/// Let the user choose from a list of rows, returning the name (first column) of their choice.
+///
+/// Rows are sorted with the most recently used names first, and the remaining columns
+/// are shown alongside the name.
+pub fn pick(kind: Kind, mut rows: Vec<Vec<String>>) -> Result<String> {
+    use dialoguer::FuzzySelect;
+
+    if !std::io::stdout().is_terminal() || !std::io::stdin().is_terminal() {
+        let err = eyre!("No {} name provided", kind.noun())
+            .suggestion("Provide a name, or run Box in a terminal to choose one interactively.");
+
+        return Err(err)
+    }
+
+    if rows.is_empty() {
+        bail!("There are no {}s to choose from", kind.noun())
+    }
+
+    let recent = recent(kind);
+
+    rows.sort_by_key(|r| (
+        recent
+            .iter()
+            .position(|n| *n == r[0])
+            .unwrap_or(usize::MAX),
+        r[0].clone()
+    ));
+
+    let columns = rows
+        .iter()
+        .map(Vec::len)
+        .max()
+        .unwrap_or_default();
+
+    let widths: Vec<_> = (0..columns)
+        .map(|i| {
+            rows
+                .iter()
+                .filter_map(|r| r.get(i))
+                .map(|c| c.chars().count())
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+
+    let items: Vec<_> = rows
+        .iter()
+        .map(|r| {
+            r
+                .iter()
+                .zip(&widths)
+                .map(|(c, w)| format!("{c:<w$}"))
+                .collect::<Vec<_>>()
+                .join("  ")
+                .trim_end()
+                .to_owned()
+        })
+        .collect();
+
+    let choice = FuzzySelect::new()
+        .with_prompt(format!("Choose a {}", kind.noun()))
+        .items(&items)
+        .default(0)
+        .interact_opt()
+        .context("Fault when asking the user to choose")?;
+
+    let Some(choice) = choice else {
+        bail!("No {} was chosen", kind.noun())
+    };
+
+    Ok(
+        rows.swap_remove(choice).swap_remove(0)
+    )
+}
@@ -1,58 +1,103 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::process::Command;
+use std::sync::LazyLock;
 
 use serde::Deserialize;
 
 use crate::prelude::*;
+use crate::cfg::*;
+use crate::cli::{ExecOptions, Overrides};
+use crate::local::Local;
+use crate::resolve;
+use crate::settings::{buildah, podman, settings};
 use crate::CommandExt;
 
 pub type Containers = Vec<Container>;
 pub type Images     = Vec<Image>;
-
-/// List of annotations that can be modified by CFG.
-pub const ANNOTATIONS: [&str; 12] = [
-    "args",
-    "cap-add",
-    "cap-drop",
-    "cpus",
-    "memory",
-    "ulimit",
-    "device",
-    "userns",
-    "security-opt",
-    "mount",
-    "restart",
-    "secret"
-];
+pub type Volumes    = Vec<Volume>;
 
 /// Represents a Podman container.
 /// 
 /// Deserialized from Podman command line JSON; not guaranteed to be up to date!
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Container {
     pub id          : String,
     pub image       : String,
     pub state       : String,
     pub annotations : HashMap<String, String>,
+    /// The command line used to create the container.
+    pub command     : Vec<String>,
+    /// The names of the networks the container is attached to.
+    pub networks    : Vec<String>,
+    /// The result of the container's health check, if it has one.
+    pub health      : Option<Health>,
+}
+
+/// The health of a container, as reported by its health check.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Health {
+    /// One of `starting`, `healthy` or `unhealthy` (or empty, if the check has never run.)
+    #[serde(rename = "Status", default)]
+    pub status : String,
+    /// The results of the most recent health checks, oldest first.
+    #[serde(rename = "Log", default)]
+    pub log    : Option<Vec<HealthLog>>,
+}
+
+/// The result of a single health check.
+#[derive(Debug, Clone, Deserialize)]
+pub struct HealthLog {
+    #[serde(rename = "ExitCode", default)]
+    pub exit_code : i64,
+    #[serde(rename = "Output", default)]
+    pub output    : String,
 }
 
 impl Container {
-    /// Given an ID (hash or human-readable name), attempt to fetch and deserialize the corresponding
-    /// container.
+    /// Given a name, a unique prefix of a name or ID, or a glob matching exactly one name,
+    /// attempt to find the corresponding container *managed by Box*.
     pub fn from_id(id: &str) -> Result<Self> {
+        // Exact names (the common case) can be looked up directly, rather than inspecting every container.
+        if let Ok(ctr) = Self::inspect(id) {
+            if ctr.annotation("manager") == Some("box") && ctr.annotation("box.name") == Some(id) {
+                return Ok(ctr)
+            }
+        }
+
+        let ctrs = Self::enumerate()?;
+
+        resolve::one("container", id, &ctrs).cloned()
+    }
+
+    /// Given an exact ID (hash or human-readable name), attempt to fetch and deserialize the corresponding
+    /// container.
+    /// 
+    /// Unlike [`Container::from_id`], this does not check that the container is managed by Box.
+    pub fn inspect(id: &str) -> Result<Self> {
         // These structs are all intermediary; they are only needed
         // to represent the nested nature of Podman's JSON output.
 
         #[derive(Debug, Deserialize)]
         struct State {
             #[serde(rename = "Status")]
-            status: String
+            status: String,
+            // Older versions of Podman use a different key.
+            #[serde(rename = "Health", alias = "Healthcheck", default)]
+            health: Option<Health>
         }
 
         #[derive(Debug, Deserialize)]
         struct Config {
             #[serde(rename = "Annotations")]
-            annotations: HashMap<String, String>
+            annotations: HashMap<String, String>,
+            #[serde(rename = "CreateCommand", default)]
+            command: Vec<String>
+        }
+
+        #[derive(Debug, Default, Deserialize)]
+        struct NetworkSettings {
+            #[serde(rename = "Networks", default)]
+            networks: Option<HashMap<String, serde_json::Value>>
         }
         
         #[derive(Debug, Deserialize)]
@@ -64,10 +109,12 @@ impl Container {
             #[serde(rename = "ImageName")]
             image : String,
             #[serde(rename = "Config")]
-            config: Config
+            config: Config,
+            #[serde(rename = "NetworkSettings", default)]
+            network: NetworkSettings
         }
 
-        let raw_json = Command::new("podman")
+        let raw_json = podman()
             .args([
                 "inspect",
                 "--type",
@@ -83,7 +130,7 @@ impl Container {
         let mut raw: Vec<Raw> = serde_json::from_str(&raw_json)
                 .context("Failed to deserialize container JSON")?;
 
-        let Raw { id, image, state, config } = raw
+        let Raw { id, image, state, config, network } = raw
                 .pop()
                 .expect("Container JSON should always have at least one element");
 
@@ -91,13 +138,16 @@ impl Container {
             id,
             image,
             state       : state.status,
-            annotations : config.annotations
+            annotations : config.annotations,
+            command     : config.command,
+            networks    : network.networks.unwrap_or_default().into_keys().collect(),
+            health      : state.health.filter(|h| !h.status.is_empty() || h.log.is_some())
         })
     }
 
     /// Enumerate all containers *managed by Box* (**NOT** every container on the system.)
     pub fn enumerate() -> Result<Containers> {
-        let o = Command::new("podman")
+        let o = podman()
             .args([
                 "ps",
                 "-a",
@@ -111,7 +161,7 @@ impl Container {
                 
         for container in o
             .lines()
-            .map(Container::from_id) 
+            .map(Container::inspect) 
         {   
             let container = container?;
 
@@ -135,7 +185,7 @@ impl Container {
 
     /// Check whether or not a container with the provided ID exists.
     pub fn exists(id: &str) -> Result<bool> {
-        let output = Command::new("podman")
+        let output = podman()
             .args([
                 "container",
                 "exists",
@@ -149,6 +199,11 @@ impl Container {
         )
     }
 
+    /// Check if the container has stopped running of its own accord (`exited` or `stopped` state.)
+    pub fn exited(&self) -> bool {
+        self.state == "exited" || self.state == "stopped"
+    }
+
     /// Check if the container is started (`running` state.)
     pub fn started(&self) -> bool {
         self.state == "running"
@@ -167,7 +222,7 @@ impl Container {
     pub fn start(&self) -> Result<()> {
         debug!("Starting container {}...", self.id);
 
-        Command::new("podman")
+        podman()
             .arg("start")
             .arg(&self.id)
             .output_ok()
@@ -180,12 +235,12 @@ impl Container {
     pub fn restart(&self) -> Result<()> {
         debug!("Restarting container {}...", self.id);
 
-        Command::new("podman")
+        podman()
             .args([
                 "restart",
-                "-t",
-                "0"
+                "-t"
             ])
+            .arg(settings().stop_timeout.to_string())
             .arg(&self.id)
             .output_ok()
             .context("Failed to restart container")?;
@@ -197,12 +252,12 @@ impl Container {
     pub fn stop(&self) -> Result<()> {
         debug!("Stopping container {}...", self.id);
 
-        Command::new("podman")
+        podman()
             .args([
                 "stop",
-                "-t",
-                "0"
+                "-t"
             ])
+            .arg(settings().stop_timeout.to_string())
             .arg(&self.id)
             .output_ok()
             .context("Failed to stop container")?;
@@ -211,72 +266,160 @@ impl Container {
     }
 
     /// Remove the container.
+    /// 
+    /// If the container was the last member of a pod created by Box, the pod is removed too.
     pub fn down(&self) -> Result<()> {
         debug!("Removing container {}...", self.id);
         
-        Command::new("podman")
+        podman()
            .args([
                 "rm",
-                "-ft",
-                "0"
+                "-ft"
            ])
+           .arg(settings().stop_timeout.to_string())
            .arg(&self.id)
            .output_ok()
            .context("Failed to remove container")?;
 
+        if let Some(pod) = self.annotation("box.pod") {
+            prune_pod(pod)?;
+        }
+
+        for secret in self
+            .annotation("box.secrets")
+            .unwrap_or_default()
+            .split('\x1F')
+            .filter(|s| !s.is_empty())
+        {
+            remove_secret(secret)?;
+        }
+
         Ok(())
     }
 
-    /// Execute `$SHELL` inside the container.
+    /// Prepare a command that prints the container's output.
     /// 
-    /// The value of `$SHELL` inside the container is used rather than the one on the host.
-    pub fn enter(&self) -> Result<()> {
-            Command::new("podman")
-                .arg("exec")
-                .arg("-it")
-                .arg(&self.id)
-                .arg("sh")
-                .arg("-c")
-                .arg("exec $SHELL")
-                .spawn()
-                .context("Fault when spawning shell inside container")?
-                .wait()?;
-        
-        Ok(())
+    /// `since` is passed to `podman logs` verbatim.
+    pub fn logs(&self, follow: bool, since: Option<&str>) -> Command {
+        let mut c = podman();
+
+        c.arg("logs");
+
+        if follow {
+            c.arg("--follow");
+        }
+
+        if let Some(since) = since {
+            c.arg("--since").arg(since);
+        }
+
+        c.arg(&self.id);
+
+        c
+    }
+
+    /// Get the runtime overrides the container was created with, if any.
+    pub fn overrides(&self) -> Overrides {
+        self
+            .annotation("box.overrides")
+            .and_then(|o| serde_json::from_str(o).ok())
+            .unwrap_or_default()
     }
 
-    /// Execute the provided command inside the container.
-    pub fn exec(&self, path: &str, args: &[String]) -> Result<()> {
-        Command::new("podman")
+    /// Execute `$SHELL` (or the provided shell) inside the container, optionally as a login shell.
+    /// 
+    /// The value of `$SHELL` inside the container is used rather than the one on the host.
+    /// 
+    /// Returns the exit code of the shell.
+    pub fn enter(&self, options: &ExecOptions, shell: Option<&str>, login: bool) -> Result<i32> {
+        let mut c = podman();
+
+        c
             .arg("exec")
-            .arg("-it")
+            .args(stdio_flags())
+            .args(exec_args(options))
             .arg(&self.id)
-            .arg(path)
-            .args(args)
-            .spawn()
-            .context("Fault when spawning process inside container")?
-            .wait()?;
-        
-        Ok(())
+            .arg("sh")
+            .arg("-c")
+            .arg(r#"shell=${1:-$SHELL}; shift; exec "$shell" "$@""#)
+            .arg("sh")
+            .arg(shell.unwrap_or_default());
+
+        if login {
+            c.arg("-l");
+        }
+
+        exit_code(&mut c)
+            .context("Fault when spawning shell inside container")
+    }
+
+    /// Execute the provided command (program and arguments) inside the container, returning its exit code.
+    /// 
+    /// If `detach` is true, the command is left running in the background.
+    pub fn exec(&self, options: &ExecOptions, detach: bool, command: &[String]) -> Result<i32> {
+        let mut c = podman();
+
+        c.arg("exec");
+
+        match detach {
+            true  => c.arg("--detach"),
+            false => c.args(stdio_flags())
+        };
+
+        c
+            .args(exec_args(options))
+            .arg(&self.id)
+            .args(command);
+
+        exit_code(&mut c)
+            .context("Fault when spawning process inside container")
     }
 }
 
 /// Represents a Podman OCI iamge.
 /// 
 /// Deserialized from Podman command line JSON; not guaranteed to be up to date!
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Image {
     #[serde(rename = "Id")]
     pub id          : String,
     #[serde(rename = "Annotations")]
     pub annotations : HashMap<String, String>,
+    #[serde(rename = "Config", default)]
+    pub config      : ImageConfig,
+    /// The fully qualified names the image is tagged with (e.g. `localhost/base:latest`.)
+    #[serde(rename = "RepoTags", default)]
+    pub tags        : Vec<String>,
+}
+
+/// The subset of an OCI image's configuration that Box cares about.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ImageConfig {
+    /// Ports declared by `EXPOSE`, in `port/protocol` form.
+    #[serde(rename = "ExposedPorts", default)]
+    pub ports : Option<HashMap<String, serde_json::Value>>,
 }
 
 impl Image {
-    /// Given an ID (hash or human-readable name), attempt to fetch and deserialize the corresponding
-    /// image.
+    /// Given a name, a unique prefix of a name or ID, or a glob matching exactly one name,
+    /// attempt to find the corresponding image *managed by Box*.
     pub fn from_id(id: &str) -> Result<Self> {
-        let raw_json = Command::new("podman")
+        // Exact names (the common case) can be looked up directly, rather than inspecting every image.
+        if let Ok(image) = Self::inspect(id) {
+            if image.annotation("manager") == Some("box") && image.annotation("box.name") == Some(id) {
+                return Ok(image)
+            }
+        }
+
+        let images = Self::enumerate()?;
+
+        resolve::one("image", id, &images).cloned()
+    }
+
+    /// Given an exact ID (hash or human-readable name), attempt to fetch and deserialize the corresponding
+    /// image.
+    fn inspect(id: &str) -> Result<Self> {
+        let raw_json = podman()
             .args([
                 "inspect",
                 "--type",
@@ -301,7 +444,7 @@ impl Image {
 
     /// Enumerate all images *managed by Box* (**NOT** every image on the system.)
     pub fn enumerate() -> Result<Images> {
-        let o = Command::new("podman")
+        let o = podman()
             .args([
                 "image",
                 "ls",
@@ -317,7 +460,7 @@ impl Image {
             
         for image in o
             .lines()
-            .map(Image::from_id) 
+            .map(Image::inspect) 
         {   
             let image = image?;
 
@@ -334,42 +477,155 @@ impl Image {
         Ok(out)
     }
 
-    /// Instantiate a container from the image, with no special arguments.
+    /// Instantiate a container from the image, with any per-host and runtime overrides
+    /// merged into its configuration.
     /// 
     /// `replace` controls whether or not the new container should overwrite
-    /// an existing one with the same name.
-    pub fn instantiate(&self, replace: bool) -> Result<()> {
-        self.instantiate_ext(replace, &[])
+    /// an existing one with the same name. If `ephemeral_args` is not empty, it replaces the
+    /// default command and the container is removed once it exits (ephemeral mode.)
+    /// 
+    /// In ephemeral mode, returns the exit code of the command; otherwise, returns zero.
+    pub fn instantiate_ext(&self, replace: bool, overrides: &Overrides, ephemeral_args: &[String]) -> Result<i32> {
+        let name = self.annotation("box.name")
+            .expect("Name annotation should be set");
+
+        let local  = Local::load(name)?;
+        let values = |option| self.values(option, &local, overrides);
+
+        for volume in named_volumes(values("mount")) {
+            Volume::ensure(volume, name)?;
+        }
+
+        let secrets = secret_sources(values("secret-from"));
+
+        for (secret, kind, spec) in &secrets {
+            provision_secret(self, secret, kind, spec)?;
+        }
+
+        let networks = values("network");
+
+        // Network modes (like host) aren't networks, so there's nothing to create.
+        for network in networks.iter().filter(|n| !is_network_mode(n)) {
+            ensure_network(network)?;
+        }
+
+        if let Some(pod) = values("pod").pop() {
+            let (mut ports, _) = split_publish(
+                values("args")
+            );
+
+            ports.extend(
+                values("publish")
+                    .into_iter()
+                    .map(str::to_owned)
+            );
+
+            ensure_pod(pod, &ports, &networks)?;
+        }
+
+        let mut c = podman();
+
+        c.args(
+            self.command_line(replace, overrides, ephemeral_args)?
+        );
+
+        if ephemeral_args.is_empty() {
+            c
+                .output_ok()
+                .context("Fault when instantiating image")?;
+
+            return Ok(0)
+        }
+
+        let code = exit_code(&mut c)
+            .context("Fault when instantiating image")?;
+
+        // Ephemeral containers are removed on exit, so their secrets are no longer needed -
+        // unless the box also has a regular container using them.
+        if !Container::exists(name)? {
+            for (secret, _, _) in secrets {
+                remove_secret(&scoped_secret(name, secret))?;
+            }
+        }
+
+        Ok(code)
     }
 
-    /// Extended instantiation method, with support for overriding the default command
-    /// (ephemeral mode.)
-    pub fn instantiate_ext(&self, replace: bool, ephemeral_args: &[String]) -> Result<()> {
+    /// Compute the arguments to `podman` that [`instantiate_ext`](Image::instantiate_ext) would use
+    /// to create a container from this image.
+    pub fn command_line(&self, replace: bool, overrides: &Overrides, ephemeral_args: &[String]) -> Result<Vec<String>> {
         let name = self.annotation("box.name")
             .expect("Name annotation should be set");
 
         let hash = self.annotation("box.hash")
             .expect("Hash annotation should be set");
 
+        let local  = Local::load(name)?;
+        let values = |option| self.values(option, &local, overrides);
+
+        let pod = values("pod").pop();
+
         let mut args = vec![];
 
         for a in ANNOTATIONS {
-            let key = format!("box.{a}");
-            
-            let Some(value) = self.annotation(&key) else {
-                continue
-            };
+            let mut values = values(a.name);
 
-            if a == "args" {
-                for v in value.split('\x1F') {
-                    args.push(v.to_owned())
-                }
-            } else {
-                let flag = format!("--{a}");
+            // Presets write annotations directly, so single-valued options may still
+            // have accumulated more than one value.
+            if !a.repeat && values.len() > 1 {
+                values.drain(..values.len() - 1);
+            }
 
-                for v in value.split('\x1F') {
-                    args.push(flag.clone());
-                    args.push(v.to_owned());
+            match (a.name, a.flag) {
+                // Handled below.
+                ("pod", _) => (),
+                ("secret-from", _) => {
+                    for (secret, _, _) in secret_sources(values) {
+                        args.push(format!(
+                            "--secret={},target={secret}",
+                            scoped_secret(name, secret)
+                        ));
+                    }
+                },
+                // Containers in a pod share its network and UTS namespaces, so networks and
+                // ports are attached to the pod instead (see `ensure_pod`) - and hostnames can't be set at all.
+                ("hostname", _) if pod.is_some() => {
+                    for v in values {
+                        crate::warning(
+                            "box",
+                            name,
+                            &format!("joins a pod, so its hostname ({v}) is ignored"),
+                            Some("Members of a pod share the pod's hostname.")
+                        );
+                    }
+                },
+                ("network" | "publish", _) if pod.is_some() => (),
+                ("network", _) if !values.is_empty() => {
+                    // Lets other boxes on the same network(s) resolve this one by name.
+                    // Aliases only apply to actual networks, rather than modes like host.
+                    if values.iter().any(|v| !is_network_mode(v)) {
+                        args.push(format!("--network-alias={name}"));
+                    }
+
+                    for v in values {
+                        args.push(format!("--network={v}"));
+                    }
+                },
+                (_, Some(flag)) => {
+                    for v in values {
+                        args.push(format!("--{flag}={v}"));
+                    }
+                },
+                (_, None) => {
+                    let values = values
+                        .into_iter()
+                        .map(str::to_owned);
+
+                    // Ports can only be published on the pod as a whole.
+                    match pod {
+                        Some(_) => args.extend(split_publish(values).1),
+                        None    => args.extend(values)
+                    }
                 }
             }
         }
@@ -380,34 +636,77 @@ impl Image {
             )
         }
 
-        let name_args = match ephemeral_args.is_empty() {
-            false => vec!["--rm", "-it", "--hostname", name],
-            true  => vec!["-d", "--name", name, "--hostname", name]
+        let mut name_args = match ephemeral_args.is_empty() {
+            false => [["--rm"].as_slice(), stdio_flags()].concat(),
+            true  => vec!["-d", "--name", name]
         };
 
-        let mut c = Command::new("podman");
+        // Containers in a pod share its UTS namespace, so they can't have their own hostname.
+        match pod {
+            Some(pod) => name_args.extend(["--pod", pod]),
+            None if values("hostname").is_empty() => {
+                name_args.extend(["--hostname", name])
+            },
+            None => ()
+        }
 
-        c
-            .arg("run")
-            .args(name_args)
-            .args(args)
-            .args([
-                "--annotation",
-                "manager=box"
-            ])
-            .arg("--annotation")
-            .arg(format!("box.name={name}"))
-            .arg("--annotation")
-            .arg(format!("box.hash={hash}"))
-            .arg(name)
-            .args(ephemeral_args);
+        let mut out: Vec<String> = vec!["run".to_owned()];
 
-        match ephemeral_args.is_empty() {
-            false =>  c.spawn_ok(),
-            true  => c.output_ok().map(drop)
-        }.context("Fault when instantiating image")?;
+        out.extend(
+            name_args.into_iter().map(str::to_owned)
+        );
 
-        Ok(())
+        out.extend(args);
+
+        out.extend([
+            "--annotation".to_owned(),
+            "manager=box".to_owned(),
+            "--annotation".to_owned(),
+            format!("box.name={name}"),
+            "--annotation".to_owned(),
+            format!("box.hash={hash}"),
+        ]);
+
+        if let Some(pod) = pod {
+            out.extend([
+                "--annotation".to_owned(),
+                format!("box.pod={pod}")
+            ]);
+        }
+
+        // Recorded so `bx reup` can apply the same overrides again.
+        if !overrides.is_empty() {
+            out.extend([
+                "--annotation".to_owned(),
+                format!(
+                    "box.overrides={}",
+                    serde_json::to_string(overrides).expect("Overrides should serialize")
+                )
+            ]);
+        }
+
+        let secrets: Vec<_> = secret_sources(values("secret-from"))
+            .into_iter()
+            .map(|(secret, _, _)| scoped_secret(name, secret))
+            .collect();
+
+        // Recorded so the secrets can be cleaned up alongside the container.
+        if !secrets.is_empty() {
+            out.extend([
+                "--annotation".to_owned(),
+                format!("box.secrets={}", secrets.join("\x1F"))
+            ]);
+        }
+
+        out.push(
+            name.to_owned()
+        );
+
+        out.extend(
+            ephemeral_args.iter().cloned()
+        );
+
+        Ok(out)
     }
 
     /// Get the value of an annotation, if it exists.
@@ -417,6 +716,678 @@ impl Image {
             .get(key)
             .map(String::as_str)
     }
+
+    /// Get the names of all named volumes mounted by containers created from this image,
+    /// taking the provided per-host overrides into account.
+    pub fn volumes(&self, local: &Local) -> Vec<String> {
+        named_volumes(self.configured_values("mount", local))
+            .into_iter()
+            .map(str::to_owned)
+            .collect()
+    }
+
+    /// Get the values of a `CFG` option as they apply on this host - including defaults from the settings file
+    /// and per-host overrides, but not one-off runtime overrides.
+    pub fn configured_values<'a>(&'a self, option: &str, local: &'a Local) -> Vec<&'a str> {
+        static NONE: LazyLock<Overrides> = LazyLock::new(Overrides::default);
+
+        self.values(option, local, &NONE)
+    }
+
+    /// Get the values of a `CFG` option (or its default from the settings file, if the image doesn't set it),
+    /// merged with any per-host and runtime overrides (in that order.)
+    fn values<'a>(&'a self, option: &str, local: &'a Local, overrides: &'a Overrides) -> Vec<&'a str> {
+        let mut values = self.annotation_values(
+            &format!("box.{option}")
+        );
+
+        if values.is_empty() {
+            values = settings().default_values(option);
+        }
+
+        local.apply(option, &mut values);
+
+        if option == "mount" && overrides.no_preset_mounts {
+            let presets: HashSet<_> = self
+                .annotation_values("box.presets")
+                .into_iter()
+                .flat_map(|p| self.annotation_values(&format!("box.preset.{p}")))
+                .filter_map(|v| v.strip_prefix("mount="))
+                .collect();
+
+            values.retain(|v| !presets.contains(v));
+        }
+
+        let extra = match option {
+            "mount"   => &overrides.mount,
+            "env"     => &overrides.env,
+            "publish" => &overrides.publish,
+            "args"    => &overrides.arg,
+            _ => return values
+        };
+
+        values.extend(
+            extra.iter().map(String::as_str)
+        );
+
+        values
+    }
+
+    /// Get the decoded list of values stored in an annotation by [`push_annotation`].
+    /// 
+    /// Returns an empty list if the annotation does not exist.
+    pub fn annotation_values(&self, key: &str) -> Vec<&str> {
+        self
+            .annotation(key)
+            .map(|v| {
+                v
+                    .split('\x1F')
+                    .filter(|v| !v.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// Represents a Podman volume.
+/// 
+/// Deserialized from Podman command line JSON; not guaranteed to be up to date!
+#[derive(Debug, Deserialize)]
+pub struct Volume {
+    #[serde(rename = "Name")]
+    pub name   : String,
+    #[serde(rename = "Labels", default)]
+    pub labels : Option<HashMap<String, String>>,
+}
+
+impl Volume {
+    /// Enumerate all volumes *created by Box* (**NOT** every volume on the system.)
+    pub fn enumerate() -> Result<Volumes> {
+        let raw_json = podman()
+            .args([
+                "volume",
+                "ls",
+                "--filter",
+                "label=manager=box",
+                "--format",
+                "json"
+            ])
+            .output_ok()
+            .context("Failed to enumerate volumes")?;
+
+        let volumes = serde_json::from_str(&raw_json)
+            .context("Failed to deserialize volume JSON")?;
+
+        Ok(volumes)
+    }
+
+    /// Create a volume owned by the named box, if one with the provided name doesn't already exist.
+    pub fn ensure(name: &str, owner: &str) -> Result<()> {
+        let exists = podman()
+            .args(["volume", "exists", name])
+            .output()
+            .context("Failed to check if volume exists")?
+            .status
+            .success();
+
+        if exists {
+            return Ok(())
+        }
+
+        debug!("Creating volume {name} for {owner}...");
+
+        podman()
+            .args([
+                "volume",
+                "create",
+                "--label",
+                "manager=box",
+                "--label"
+            ])
+            .arg(format!("box.name={owner}"))
+            .arg(name)
+            .output_ok()
+            .context("Fault when creating volume")?;
+
+        Ok(())
+    }
+
+    /// Get the name of the box that owns the volume, if any.
+    pub fn owner(&self) -> Option<&str> {
+        self
+            .labels
+            .as_ref()?
+            .get("box.name")
+            .map(String::as_str)
+    }
+
+    /// Get the IDs of every container (managed by Box or not) that mounts the volume.
+    pub fn users(&self) -> Result<Vec<String>> {
+        let ids = podman()
+            .args([
+                "ps",
+                "--all",
+                "--no-trunc",
+                "--filter",
+                &format!("volume={}", self.name),
+                "--format",
+                "{{.ID}}"
+            ])
+            .output_ok()
+            .context("Failed to enumerate containers using volume")?;
+
+        Ok(
+            ids.lines().map(str::to_owned).collect()
+        )
+    }
+
+    /// Remove the volume.
+    /// 
+    /// Fails if the volume is still in use by any container.
+    pub fn remove(&self) -> Result<()> {
+        debug!("Removing volume {}...", self.name);
+
+        podman()
+            .args(["volume", "rm", &self.name])
+            .output_ok()
+            .context("Failed to remove volume")?;
+
+        Ok(())
+    }
+}
+
+/// Parse the values of the `secret-from` option into `(name, source kind, source)` triples.
+fn secret_sources(values: Vec<&str>) -> Vec<(&str, &str, &str)> {
+    values
+        .into_iter()
+        .filter_map(|v| {
+            let (secret, source) = v.split_once('=')?;
+            let (kind, spec) = source.split_once(':')?;
+
+            Some((secret, kind, spec))
+        })
+        .collect()
+}
+
+/// Get the names of all named volumes used by a list of mounts (in `--mount` syntax.)
+fn named_volumes<'a>(mounts: impl IntoIterator<Item = &'a str>) -> Vec<&'a str> {
+    let mut out = vec![];
+
+    for mount in mounts {
+        let mut volume = false;
+        let mut source = None;
+
+        for option in mount.split(',') {
+            match option.split_once('=') {
+                Some(("type", kind)) => volume = kind == "volume",
+                Some(("src" | "source", src)) => source = Some(src),
+                _ => ()
+            }
+        }
+
+        if let (true, Some(source)) = (volume, source) {
+            out.push(source)
+        }
+    }
+
+    out
+}
+
+/// Flags for `podman exec` and `podman run` that connect the container to our standard streams.
+/// 
+/// Standard input is always passed through, but a TTY is only requested when both standard input
+/// and output are terminals - otherwise, Box couldn't be used in pipes or scripts.
+fn stdio_flags() -> &'static [&'static str] {
+    use std::io::IsTerminal;
+
+    if std::io::stdin().is_terminal() && std::io::stdout().is_terminal() {
+        &["-i", "-t"]
+    }
+    else {
+        &["-i"]
+    }
+}
+
+/// Translate per-invocation options for `bx exec` and `bx enter` into `podman exec` flags.
+fn exec_args(options: &ExecOptions) -> Vec<String> {
+    let mut args = vec![];
+
+    if options.root {
+        args.push("--user=root".to_owned());
+    }
+
+    if let Some(user) = &options.user {
+        args.push(format!("--user={user}"));
+    }
+
+    for env in &options.env {
+        args.push(format!("--env={env}"));
+    }
+
+    for file in &options.env_file {
+        args.push(format!("--env-file={file}"));
+    }
+
+    if let Some(workdir) = &options.workdir {
+        args.push(format!("--workdir={workdir}"));
+    }
+
+    args
+}
+
+/// Run a command with inherited standard streams, returning its exit code.
+/// 
+/// Like a shell, termination by a signal is reported as 128 plus the signal number.
+pub fn exit_code(c: &mut Command) -> Result<i32> {
+    use std::os::unix::process::ExitStatusExt;
+
+    debug!("Shelling out; command is {c:?}");
+
+    let status = c
+        .spawn()?
+        .wait()?;
+
+    let code = status
+        .code()
+        .or_else(|| status.signal().map(|s| 128 + s))
+        .unwrap_or(1);
+
+    Ok(code)
+}
+
+/// Splits a list of `podman run` arguments into the values of any `-p`/`--publish` flags
+/// and everything else.
+pub fn split_publish<S>(args: impl IntoIterator<Item = S>) -> (Vec<String>, Vec<String>)
+where
+    S: Into<String>
+{
+    let mut ports = vec![];
+    let mut rest  = vec![];
+
+    let mut iter = args
+        .into_iter()
+        .map(Into::into);
+
+    while let Some(arg) = iter.next() {
+        if arg == "-p" || arg == "--publish" {
+            ports.extend(iter.next());
+        }
+        else if let Some(port) = arg.strip_prefix("--publish=") {
+            ports.push(port.to_owned());
+        }
+        else if let Some(port) = arg.strip_prefix("-p") {
+            ports.push(
+                port.trim_start_matches('=').to_owned()
+            );
+        }
+        else {
+            rest.push(arg);
+        }
+    }
+
+    (ports, rest)
+}
+
+/// Create a pod managed by Box with the provided name, published ports and networks,
+/// if one doesn't already exist.
+pub fn ensure_pod(name: &str, ports: &[String], networks: &[&str]) -> Result<()> {
+    let exists = podman()
+        .args(["pod", "exists", name])
+        .output()
+        .context("Failed to check if pod exists")?
+        .status
+        .success();
+
+    // Ports and networks can only be set when a pod is created, so the ones it was created with
+    // are recorded as labels - anything else requested of an existing pod can't be applied.
+    if exists {
+        let labels = podman()
+            .args(["pod", "inspect", "--format", "{{json .Labels}}", name])
+            .output_ok()
+            .context("Failed to inspect pod labels")?;
+
+        let labels: HashMap<String, String> = serde_json::from_str::<Option<_>>(&labels)
+            .context("Failed to deserialize pod labels")?
+            .unwrap_or_default();
+
+        let recorded = |key: &str| -> Vec<String> {
+            labels
+                .get(key)
+                .map(|v| v.split('\x1F').filter(|v| !v.is_empty()).map(str::to_owned).collect())
+                .unwrap_or_default()
+        };
+
+        let (published, attached) = (recorded("box.publish"), recorded("box.network"));
+
+        let mut missing: Vec<_> = ports
+            .iter()
+            .filter(|p| !published.contains(p))
+            .map(|p| format!("port {p}"))
+            .collect();
+
+        missing.extend(
+            networks
+                .iter()
+                .filter(|n| !attached.iter().any(|a| a == *n))
+                .map(|n| format!("network {n}"))
+        );
+
+        if !missing.is_empty() {
+            crate::warning(
+                "pod",
+                name,
+                &format!("already exists, so {} can't be applied", missing.join(", ")),
+                Some("Recreate the pod by running 'bx down' on all of its members, then 'bx up' again.")
+            );
+        }
+
+        return Ok(())
+    }
+
+    debug!("Creating pod {name}...");
+
+    let mut c = podman();
+
+    c.args([
+        "pod",
+        "create",
+        "--name",
+        name,
+        "--label",
+        "manager=box",
+        "--label",
+        &format!("box.publish={}", ports.join("\x1F")),
+        "--label",
+        &format!("box.network={}", networks.join("\x1F"))
+    ]);
+
+    for port in ports {
+        c.arg("--publish").arg(port);
+    }
+
+    for network in networks {
+        c.arg("--network").arg(network);
+    }
+
+    // Containers in a pod share its network namespace, so their aliases have to be set on the pod.
+    // Only boxes that have been built by the time the pod is created are included.
+    if networks.iter().any(|n| !is_network_mode(n)) {
+        for image in Image::enumerate()? {
+            let Some(member) = image.annotation("box.name") else {
+                continue
+            };
+
+            let local = Local::load(member)?;
+
+            if image.configured_values("pod", &local).last() == Some(&name) {
+                c.arg("--network-alias").arg(member);
+            }
+        }
+    }
+
+    c
+        .output_ok()
+        .context("Fault when creating pod")?;
+
+    Ok(())
+}
+
+/// Remove a pod created by Box if it no longer has any (non-infra) containers.
+pub fn prune_pod(name: &str) -> Result<()> {
+    let managed = podman()
+        .args([
+            "pod",
+            "ps",
+            "--filter",
+            "label=manager=box",
+            "--format",
+            "{{.Name}}"
+        ])
+        .output_ok()
+        .context("Failed to enumerate pods")?
+        .lines()
+        .any(|l| l == name);
+
+    if !managed {
+        return Ok(())
+    }
+
+    let members = podman()
+        .args([
+            "ps",
+            "-a",
+            "--filter",
+            &format!("pod={name}"),
+            "--format",
+            "{{.IsInfra}}"
+        ])
+        .output_ok()
+        .context("Failed to enumerate pod members")?
+        .lines()
+        .filter(|l| *l == "false")
+        .count();
+
+    if members == 0 {
+        debug!("Removing empty pod {name}...");
+
+        podman()
+            .args(["pod", "rm", "-f", name])
+            .output_ok()
+            .context("Failed to remove empty pod")?;
+    }
+
+    Ok(())
+}
+
+/// Get the name of the Podman secret Box provisions for a box, so that
+/// secrets with the same name in different boxes don't collide.
+pub fn scoped_secret(owner: &str, secret: &str) -> String {
+    format!("box-{owner}-{secret}")
+}
+
+/// Create (or update) the Podman secret for a box from the provided source on the host.
+/// 
+/// Relative file paths and commands are resolved against the directory containing the definition.
+pub fn provision_secret(image: &Image, secret: &str, kind: &str, spec: &str) -> Result<()> {
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::process::Stdio;
+
+    let owner = image.annotation("box.name")
+        .expect("Name annotation should be set");
+
+    let dir = image
+        .annotation("box.path")
+        .map(PathBuf::from)
+        .and_then(|p| p.parent().map(PathBuf::from))
+        .unwrap_or_default();
+
+    let data = match kind {
+        "file" => std::fs::read(dir.join(spec))
+            .context(format!("Failed to read secret {secret} from file {spec}"))?,
+        "env"  => std::env::var(spec)
+            .context(format!("Failed to read secret {secret} from environment variable {spec}"))
+            .suggestion("Is it set?")?
+            .into_bytes(),
+        "cmd"  => {
+            let o = Command::new("sh")
+                .arg("-c")
+                .arg(spec)
+                .current_dir(&dir)
+                .output()
+                .context(format!("Fault when running command for secret {secret}"))?;
+
+            // The output may contain (part of) the secret, so only standard error is ever reported.
+            if !o.status.success() {
+                let stderr = String::from_utf8_lossy(&o.stderr)
+                    .trim()
+                    .to_owned();
+
+                let err = eyre!("Failed to read secret {secret} from command")
+                    .section(o.status.to_string().header("Status:"))
+                    .section(stderr.header("Stderr:"));
+
+                return Err(err)
+            }
+
+            let mut out = o.stdout;
+
+            // Most secret managers terminate their output with a newline.
+            if out.ends_with(b"\n") {
+                out.pop();
+            }
+
+            out
+        },
+        _ => bail!("Unknown source {kind} for secret {secret}")
+    };
+
+    let name = scoped_secret(owner, secret);
+
+    debug!("Provisioning secret {name}...");
+
+    let mut c = podman();
+
+    c
+        .args([
+            "secret",
+            "create",
+            "--replace",
+            "--label",
+            "manager=box",
+            "--label",
+        ])
+        .arg(format!("box.name={owner}"))
+        .arg(&name)
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped());
+
+    let mut child = c
+        .spawn()
+        .context("Fault when spawning secret creation")?;
+
+    child
+        .stdin
+        .take()
+        .expect("Secret creation should have piped standard input")
+        .write_all(&data)
+        .context("Fault when writing secret data")?;
+
+    let o = child
+        .wait_with_output()
+        .context("Fault when creating secret")?;
+
+    if !o.status.success() {
+        let stderr = String::from_utf8_lossy(&o.stderr)
+            .to_string()
+            .header("Standard error:");
+
+        let err = eyre!("Failed to create secret {name}")
+            .section(stderr);
+
+        return Err(err)
+    }
+
+    Ok(())
+}
+
+/// Remove a Podman secret, if it exists.
+pub fn remove_secret(name: &str) -> Result<()> {
+    let exists = podman()
+        .args(["secret", "exists", name])
+        .output()
+        .context("Failed to check if secret exists")?
+        .status
+        .success();
+
+    if exists {
+        debug!("Removing secret {name}...");
+
+        podman()
+            .args(["secret", "rm", name])
+            .output_ok()
+            .context("Failed to remove secret")?;
+    }
+
+    Ok(())
+}
+
+/// Create a network managed by Box with the provided name, if one doesn't already exist.
+pub fn ensure_network(name: &str) -> Result<()> {
+    let exists = podman()
+        .args(["network", "exists", name])
+        .output()
+        .context("Failed to check if network exists")?
+        .status
+        .success();
+
+    if exists {
+        return Ok(())
+    }
+
+    debug!("Creating network {name}...");
+
+    podman()
+        .args([
+            "network",
+            "create",
+            "--label",
+            "manager=box",
+            name
+        ])
+        .output_ok()
+        .context("Fault when creating network")?;
+
+    Ok(())
+}
+
+/// Enumerate the names of all networks *created by Box*.
+pub fn managed_networks() -> Result<Vec<String>> {
+    let o = podman()
+        .args([
+            "network",
+            "ls",
+            "--filter",
+            "label=manager=box",
+            "--format",
+            "{{.Name}}"
+        ])
+        .output_ok()
+        .context("Failed to enumerate networks")?;
+
+    Ok(
+        o.lines().map(str::to_owned).collect()
+    )
+}
+
+/// Remove all networks created by Box that no longer have any containers attached.
+pub fn prune_networks() -> Result<()> {
+    for network in managed_networks()? {
+        let attached = podman()
+            .args([
+                "ps",
+                "-a",
+                "--filter",
+                &format!("network={network}"),
+                "--format",
+                "{{.ID}}"
+            ])
+            .output_ok()
+            .context("Failed to enumerate network members")?;
+
+        if attached.trim().is_empty() {
+            debug!("Removing unused network {network}...");
+
+            podman()
+                .args(["network", "rm", &network])
+                .output_ok()
+                .context("Failed to remove unused network")?;
+        }
+    }
+
+    Ok(())
 }
 
 /// Append a value to the specified annotation on the provided container. Each item is separated with
@@ -427,7 +1398,7 @@ pub fn push_annotation(ctr: &str, key: &str, data: &str) -> Result<()> {
         key
     );
 
-    let old = Command::new("buildah")
+    let old = buildah()
         .arg("inspect")
         .arg("-t")
         .arg("container")
@@ -455,7 +1426,7 @@ pub fn write_annotation(ctr: &str, key: &str, data: Vec<&str>) -> Result<()> {
         data.join("\x1F").trim_start_matches('\x1F')
     );
 
-    Command::new("buildah")
+    buildah()
         .arg("config")
         .arg("-a")
         .arg(mapping)
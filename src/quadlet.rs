@@ -0,0 +1,261 @@
+//! Translation of Box images into Podman Quadlet (systemd) units.
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::process::Command;
+
+use crate::prelude::*;
+use crate::cfg::*;
+use crate::local::Local;
+use crate::podman::*;
+use crate::CommandExt;
+
+/// Render a Quadlet `.container` unit for the provided image.
+///
+/// If `autostart` is true, an `[Install]` section is included so the unit is started at login.
+pub fn render(image: &Image, autostart: bool) -> Result<String> {
+    let name = image.annotation("box.name")
+        .context("Image is missing its name annotation")
+        .suggestion("Was it built by Box?")?;
+
+    let hash = image.annotation("box.hash")
+        .context("Image is missing its hash annotation")
+        .suggestion("Was it built by Box?")?;
+
+    // Units should run the same configuration 'bx up' would.
+    let local  = Local::load(name)?;
+    let values = |option| image.configured_values(option, &local);
+    let pod    = values("pod").pop();
+
+    // Quadlet resolves short names through the configured registries, so use the name the image was
+    // committed under.
+    let reference = image.tags
+        .first()
+        .cloned()
+        .unwrap_or_else(|| format!("localhost/{name}"));
+
+    let mut container = vec![
+        ("Image", reference),
+        ("ContainerName", name.to_owned()),
+        ("Annotation", "manager=box".to_owned()),
+        ("Annotation", format!("box.name={name}")),
+        ("Annotation", format!("box.hash={hash}")),
+    ];
+
+    // Containers in a pod share its UTS namespace, so they can't have their own hostname.
+    match pod {
+        Some(pod) => {
+            crate::warning(
+                "image",
+                name,
+                &format!("joins pod {pod}, which must exist before the unit is started"),
+                None
+            );
+            container.push(("Annotation", format!("box.pod={pod}")));
+            container.push(("PodmanArgs", quote(&format!("--pod={pod}"))));
+        },
+        None if values("hostname").is_empty() => {
+            container.push(("HostName", name.to_owned()))
+        },
+        None => ()
+    }
+
+    let mut service = vec![];
+
+    for a in ANNOTATIONS {
+        for v in values(a.name) {
+            let v = v.to_owned();
+
+            match a.name {
+                "mount"    => container.push(("Mount", v)),
+                "userns"   => container.push(("UserNS", v)),
+                "cap-add"  => container.push(("AddCapability", v)),
+                "cap-drop" => container.push(("DropCapability", v)),
+                "device"   => container.push(("AddDevice", v)),
+                "ulimit"   => container.push(("Ulimit", v)),
+                "secret"   => container.push(("Secret", v)),
+                "secret-from" => {
+                    let secret = v.split_once('=').map_or(v.as_str(), |(s, _)| s);
+
+                    crate::warning(
+                        "image",
+                        name,
+                        &format!("uses secret {secret}, which is only provisioned by 'bx up'"),
+                        Some("Run 'bx up' once (or create the secret by hand) before starting the unit.")
+                    );
+
+                    container.push((
+                        "Secret",
+                        format!("{},target={secret}", scoped_secret(name, secret))
+                    ));
+                },
+                // Containers in a pod share its network and UTS namespaces.
+                "network" | "publish" | "hostname" if pod.is_some() => (),
+                "network"  => container.push(("Network", v)),
+                "publish"  => container.push(("PublishPort", v)),
+                "hostname" => container.push(("HostName", v)),
+                "env"      => container.push(("Environment", quote(&v))),
+                "env-file" => container.push(("EnvironmentFile", v)),
+                "dns"      => container.push(("DNS", v)),
+                "add-host" => container.push(("AddHost", v)),
+                "tmpfs"    => container.push(("Tmpfs", v)),
+                "shm-size" => container.push(("ShmSize", v)),
+                "group-add"  => container.push(("GroupAdd", v)),
+                "pids-limit" => container.push(("PidsLimit", v)),
+                "label"    => container.push(("Label", quote(&v))),
+                "init"     => container.push(("RunInit", v)),
+                "args"     => container.push(("PodmanArgs", quote(&v))),
+                // Handled above.
+                "pod"      => (),
+                "security-opt" => match v.as_str() {
+                    "label=disable" => container.push(("SecurityLabelDisable", "true".to_owned())),
+                    "no-new-privileges" | "no-new-privileges=true" => {
+                        container.push(("NoNewPrivileges", "true".to_owned()))
+                    },
+                    _ => container.push(("PodmanArgs", quote(&format!("--security-opt={v}"))))
+                },
+                "restart" => {
+                    let (policy, _) = v
+                        .split_once(':')
+                        .unwrap_or((&v, ""));
+
+                    let policy = match policy {
+                        "no"             => "no",
+                        "on-failure"     => "on-failure",
+                        "always"         => "always",
+                        "unless-stopped" => "always",
+                        _ => bail!("Unrecognized restart policy {v}")
+                    };
+
+                    if v.contains(':') {
+                        crate::warning(
+                            "image",
+                            name,
+                            &format!("has a restart retry limit ({v}), which Quadlet units do not support"),
+                            Some("The limit is ignored; set StartLimitBurst= in the unit's [Unit] section instead.")
+                        );
+                    }
+
+                    service.push(("Restart", policy.to_owned()));
+                },
+                _ => container.push(("PodmanArgs", quote(&format!("--{}={v}", a.name))))
+            }
+        }
+    }
+
+    if pod.is_none() && values("network").iter().any(|n| !is_network_mode(n)) {
+        container.push(("PodmanArgs", format!("--network-alias={name}")));
+    }
+
+    let mut out = String::new();
+
+    let mut section = |header: &str, entries: &[(&str, String)]| {
+        writeln!(out, "[{header}]").unwrap();
+
+        for (key, value) in entries {
+            writeln!(out, "{key}={value}").unwrap();
+        }
+
+        writeln!(out).unwrap();
+    };
+
+    section(
+        "Unit",
+        &[("Description", format!("Box container {name}"))]
+    );
+
+    section("Container", &container);
+
+    if !service.is_empty() {
+        section("Service", &service);
+    }
+
+    if autostart {
+        section(
+            "Install",
+            &[("WantedBy", "default.target".to_owned())]
+        );
+    }
+
+    Ok(out)
+}
+
+/// Write a Quadlet unit for the provided image into the user's Quadlet directory,
+/// returning the path of the unit file.
+pub fn install(image: &Image, autostart: bool) -> Result<PathBuf> {
+    let name = image.annotation("box.name")
+        .context("Image is missing its name annotation")?;
+
+    let unit = render(image, autostart)?;
+    let path = unit_directory()?.join(
+        format!("{name}.container")
+    );
+
+    std::fs::write(&path, unit)
+        .context("Fault when writing Quadlet unit")?;
+
+    reload()?;
+
+    Ok(path)
+}
+
+/// Remove the Quadlet unit for the container with the provided name, if one exists.
+///
+/// Returns whether or not a unit was removed.
+pub fn uninstall(name: &str) -> Result<bool> {
+    let path = unit_directory()?.join(
+        format!("{name}.container")
+    );
+
+    if !path.exists() {
+        return Ok(false)
+    }
+
+    std::fs::remove_file(&path)
+        .context("Fault when removing Quadlet unit")?;
+
+    reload()?;
+
+    Ok(true)
+}
+
+/// Determines the directory Quadlet reads user units from, creating it if necessary.
+///
+/// - `$XDG_CONFIG_HOME/containers/systemd`
+/// - `$HOME/.config/containers/systemd`
+fn unit_directory() -> Result<PathBuf> {
+    let dir = crate::build::config_home()
+        .context("Could not find a valid directory for Quadlet units")?
+        .join("containers")
+        .join("systemd");
+
+    if !dir.exists() {
+        std::fs::create_dir_all(&dir)
+            .context("Failed to create Quadlet unit directory")?;
+    }
+
+    Ok(dir)
+}
+
+/// Ask the user's systemd instance to regenerate units from Quadlet files.
+fn reload() -> Result<()> {
+    Command::new("systemctl")
+        .args(["--user", "daemon-reload"])
+        .output_ok()
+        .context("Fault when reloading systemd user units")
+        .suggestion("Is a systemd user instance running?")?;
+
+    Ok(())
+}
+
+/// Quote a value for use in a systemd unit file if it contains whitespace or quotes.
+fn quote(s: &str) -> String {
+    if s.contains(|c: char| c.is_whitespace() || c == '"' || c == '\\') {
+        format!(
+            "\"{}\"",
+            s.replace('\\', "\\\\").replace('"', "\\\"")
+        )
+    }
+    else {
+        s.to_owned()
+    }
+}
@@ -0,0 +1,245 @@
+//! Resolution of user-provided names into Box-managed objects.
+//!
+//! A name resolves to, in order of preference:
+//! - the object with exactly that name;
+//! - every object whose name matches it, if it's a glob (`*` matches any run of characters, `?` any one character);
+//! - the only object whose name starts with it;
+//! - the only object whose ID starts with it.
+//!
+//! Destructive commands (like `bx down`) use [`exact`] instead, which only accepts an exact name or ID.
+//!
+//! Only the candidates handed in are considered, so callers are responsible for filtering out
+//! anything not managed by Box.
+use crate::prelude::*;
+use crate::fuzzy::*;
+use crate::podman::*;
+
+/// Something that can be referred to by name (and possibly by ID) on the command line.
+pub trait Named {
+    /// The human-readable name of the object, if it has one.
+    fn name(&self) -> Option<&str>;
+
+    /// The ID of the object, if it has one.
+    fn id(&self) -> Option<&str> {
+        None
+    }
+}
+
+impl Named for Container {
+    fn name(&self) -> Option<&str> {
+        self.annotation("box.name")
+    }
+
+    fn id(&self) -> Option<&str> {
+        Some(&self.id)
+    }
+}
+
+impl Named for Image {
+    fn name(&self) -> Option<&str> {
+        self.annotation("box.name")
+    }
+
+    fn id(&self) -> Option<&str> {
+        Some(&self.id)
+    }
+}
+
+impl Named for String {
+    fn name(&self) -> Option<&str> {
+        Some(self)
+    }
+}
+
+/// Resolve a name, glob or prefix into every matching candidate.
+///
+/// `kind` is the noun used to describe the candidates in errors (e.g. "container".)
+pub fn all<'a, T: Named>(kind: &str, pattern: &str, candidates: &'a [T]) -> Result<Vec<&'a T>> {
+    let named = || candidates
+        .iter()
+        .filter_map(|c| Some((c.name()?, c)));
+
+    if let Some((_, c)) = named().find(|(n, _)| *n == pattern) {
+        return Ok(vec![c])
+    }
+
+    if is_glob(pattern) {
+        let matches: Vec<_> = named()
+            .filter(|(n, _)| glob(pattern, n))
+            .map(|(_, c)| c)
+            .collect();
+
+        if matches.is_empty() {
+            let err = eyre!("No {kind}s match the pattern {pattern}")
+                .suggestion(format!("Check the pattern, or list the available {kind}s."));
+
+            return Err(err)
+        }
+
+        return Ok(matches)
+    }
+
+    let by_name: Vec<_> = named()
+        .filter(|(n, _)| n.starts_with(pattern))
+        .collect();
+
+    match by_name.as_slice() {
+        [(_, c)] => return Ok(vec![*c]),
+        [] => (),
+        _ => return Err(
+            ambiguous(kind, pattern, by_name.iter().map(|(n, _)| *n))
+        )
+    }
+
+    let by_id: Vec<_> = candidates
+        .iter()
+        .filter(|c| c.id().is_some_and(|id| id.starts_with(pattern)))
+        .collect();
+
+    match by_id.as_slice() {
+        [c] => Ok(vec![*c]),
+        [] => {
+            let mut fuzzy = Fuzzy::new();
+
+            for (n, _) in named() { fuzzy.add(n); }
+
+            let err = eyre!("Tried to operate on a {kind} ({pattern}) that does not exist")
+                .suggestion(fuzzy.suggestion(pattern));
+
+            Err(err)
+        },
+        _ => Err(
+            ambiguous(kind, pattern, by_id.iter().filter_map(|c| c.id()))
+        )
+    }
+}
+
+/// Resolve a name, glob or prefix into exactly one candidate.
+pub fn one<'a, T: Named>(kind: &str, pattern: &str, candidates: &'a [T]) -> Result<&'a T> {
+    let mut matches = all(kind, pattern, candidates)?;
+
+    if matches.len() > 1 {
+        return Err(
+            ambiguous(kind, pattern, matches.iter().filter_map(|c| c.name()))
+        )
+    }
+
+    Ok(matches.remove(0))
+}
+
+/// Resolve an exact name or ID (or a prefix of an ID at least as long as the short form Podman shows)
+/// into one candidate, for operations where acting on the wrong object would be costly.
+pub fn exact<'a, T: Named>(kind: &str, pattern: &str, candidates: &'a [T]) -> Result<&'a T> {
+    let id_matches = |id: &str| id == pattern || (pattern.len() >= 12 && id.starts_with(pattern));
+
+    if let Some(c) = candidates.iter().find(|c| c.name() == Some(pattern)) {
+        return Ok(c)
+    }
+
+    if let Some(c) = candidates.iter().find(|c| c.id().is_some_and(id_matches)) {
+        return Ok(c)
+    }
+
+    // Point the user at whatever they probably meant, without acting on it.
+    let err = match all(kind, pattern, candidates) {
+        Ok(matches) => {
+            let names: Vec<_> = matches
+                .iter()
+                .filter_map(|c| c.name())
+                .collect();
+
+            eyre!("{pattern} is not the exact name of a {kind}")
+                .section(names.join("\n").header("Matches:"))
+                .suggestion(format!("This command only accepts exact names - use the full name of the {kind}."))
+        },
+        Err(err) => err
+    };
+
+    Err(err)
+}
+
+fn ambiguous<'a>(kind: &str, pattern: &str, candidates: impl Iterator<Item = &'a str>) -> Report {
+    let mut candidates: Vec<_> = candidates.collect();
+
+    candidates.sort_unstable();
+
+    eyre!("{pattern} is ambiguous - it matches more than one {kind}")
+        .section(candidates.join("\n").header("Candidates:"))
+        .suggestion(format!("Use the full name of the {kind}, or enough of it to be unique."))
+}
+
+fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?'])
+}
+
+/// Match a name against a glob, where `*` matches any run of characters and `?` matches any one character.
+fn glob(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<_> = pattern.chars().collect();
+    let name: Vec<_> = name.chars().collect();
+
+    // Position in the pattern just after the last `*`, and the position in the name it was tried against.
+    let mut star = None;
+    let (mut p, mut n) = (0, 0);
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, n));
+                p += 1;
+            },
+            Some(c) if *c == '?' || *c == name[n] => {
+                p += 1;
+                n += 1;
+            },
+            _ => match star {
+                // Let the last `*` swallow one more character and try again.
+                Some((sp, sn)) => {
+                    star = Some((sp, sn + 1));
+                    p = sp;
+                    n = sn + 1;
+                },
+                None => return false
+            }
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn globs() {
+        assert!(glob("work-*", "work-"));
+        assert!(glob("work-*", "work-rust"));
+        assert!(glob("*-dev", "web-dev"));
+        assert!(glob("w?b*v", "web-dev"));
+        assert!(glob("*", ""));
+
+        assert!(!glob("work-*", "home-rust"));
+        assert!(!glob("w?b", "wb"));
+        assert!(!glob("*-dev", "web-devel"));
+    }
+
+    #[test]
+    fn resolution() {
+        let names: Vec<_> = ["web", "web-dev", "work-rust", "work-go"]
+            .into_iter()
+            .map(str::to_owned)
+            .collect();
+
+        assert_eq!(one("box", "web", &names).unwrap(), "web");
+        assert_eq!(one("box", "web-", &names).unwrap(), "web-dev");
+        assert_eq!(all("box", "work-*", &names).unwrap().len(), 2);
+
+        assert!(one("box", "work", &names).is_err());
+        assert!(one("box", "work-*", &names).is_err());
+        assert!(all("box", "home", &names).is_err());
+
+        assert_eq!(exact("box", "web", &names).unwrap(), "web");
+        assert!(exact("box", "web-", &names).is_err());
+        assert!(exact("box", "work-*", &names).is_err());
+    }
+}
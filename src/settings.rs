@@ -0,0 +1,235 @@
+//! Settings for Box itself, read from `$XDG_CONFIG_HOME/box/config.toml` (or `$HOME/.config/box/config.toml`.)
+//!
+//! ```toml
+//! podman       = "podman"
+//! buildah      = "buildah"
+//! stop-timeout = 10
+//! parallelism  = 4
+//! color        = "auto"
+//! progress     = true
+//! format       = "table"
+//! template     = "#!/usr/bin/env fish\n\n"
+//! definitions  = "/home/me/dotfiles/box"
+//! log          = "warn"
+//!
+//! [defaults]
+//! memory = "8g"
+//! ```
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+
+use crate::prelude::*;
+use crate::cfg::*;
+use crate::cli::Format;
+use crate::fuzzy::*;
+use crate::local::Values;
+
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+
+/// When to use colour in output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Color {
+    /// Only when writing to a terminal.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// How much to log, unless overridden by `$RUST_LOG` or `-v`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    #[default]
+    #[serde(alias = "none")]
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    /// The level as understood by `$RUST_LOG`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Off   => "off",
+            Self::Error => "error",
+            Self::Warn  => "warn",
+            Self::Info  => "info",
+            Self::Debug => "debug",
+            Self::Trace => "trace",
+        }
+    }
+}
+
+/// The contents of the settings file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Settings {
+    /// The Podman binary to use.
+    pub podman       : String,
+    /// The Buildah binary to use.
+    pub buildah      : String,
+    /// How many seconds to wait for containers to stop before killing them.
+    pub stop_timeout : u32,
+    /// The initial contents of new definitions.
+    pub template     : String,
+    /// How many containers to operate on at once (e.g. when starting or stopping a set.)
+    /// 
+    /// Containers that depend on each other are still started (and stopped) in order.
+    pub parallelism  : usize,
+    /// When to use colour in output.
+    pub color        : Color,
+    /// Whether or not to show progress spinners.
+    pub progress     : bool,
+    /// The default output format for commands that support several.
+    pub format       : Format,
+    /// The directory to store definitions in, unless overridden by `$BOX_DEFINITION_DIR`.
+    pub definitions  : Option<PathBuf>,
+    /// How much to log, unless overridden by `$RUST_LOG` or `-v`.
+    pub log          : Level,
+    /// Default values for `CFG` options, used by any image that doesn't set the option itself.
+    pub defaults     : BTreeMap<String, Values>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            podman       : "podman".to_owned(),
+            buildah      : "buildah".to_owned(),
+            stop_timeout : 0,
+            template     : "#!/bin/bash\n\n".to_owned(),
+            parallelism  : 1,
+            color        : Color::Auto,
+            progress     : true,
+            format       : Format::Table,
+            definitions  : None,
+            log          : Level::Off,
+            defaults     : BTreeMap::new(),
+        }
+    }
+}
+
+impl Settings {
+    /// Load and validate the settings file, falling back to the defaults if it doesn't exist.
+    pub fn load() -> Result<Self> {
+        let path = settings_path()?;
+
+        if !path.exists() {
+            return Ok(Self::default())
+        }
+
+        let contents = std::fs::read_to_string(&path)
+            .context("Fault when reading settings file")?;
+
+        let settings: Self = toml::from_str(&contents)
+            .context("Failed to parse settings file")
+            .with_section(|| path.to_string_lossy().into_owned().header("Path:"))?;
+
+        settings
+            .validate()
+            .with_section(|| path.to_string_lossy().into_owned().header("Path:"))?;
+
+        Ok(settings)
+    }
+
+    /// Get the default values (if any) for a `CFG` option.
+    pub fn default_values(&self, option: &str) -> Vec<&str> {
+        self
+            .defaults
+            .get(option)
+            .map(Values::as_slice)
+            .unwrap_or_default()
+            .iter()
+            .map(String::as_str)
+            .collect()
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.parallelism == 0 {
+            bail!("Setting parallelism must be at least 1")
+        }
+
+        if let Some(dir) = &self.definitions {
+            if !dir.is_absolute() {
+                let err = eyre!("Setting definitions must be an absolute path")
+                    .section(dir.to_string_lossy().into_owned().header("Value:"))
+                    .suggestion("Spell out the full path - $HOME and ~ are not expanded.");
+
+                return Err(err)
+            }
+        }
+
+        for (name, values) in &self.defaults {
+            let Some(option) = Annotation::find(name) else {
+                let mut fuzzy = Fuzzy::new();
+
+                for a in ANNOTATIONS { fuzzy.add(a.name); }
+
+                let err = eyre!("Unknown configuration option {name} in [defaults]")
+                    .suggestion(fuzzy.suggestion(name));
+
+                return Err(err)
+            };
+
+            for value in values.as_slice() {
+                (option.validate)(value)
+                    .with_context(|| format!("Invalid value {value:?} for option {name} in [defaults]"))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Load, validate and apply the settings file, making it available through [`settings`].
+pub fn init() -> Result<&'static Settings> {
+    let loaded   = Settings::load()?;
+    let settings = SETTINGS.get_or_init(|| loaded);
+
+    match settings.color {
+        Color::Auto   => (),
+        Color::Always => colored::control::set_override(true),
+        Color::Never  => colored::control::set_override(false),
+    }
+
+    Ok(settings)
+}
+
+/// Get the current settings.
+///
+/// If [`init`] hasn't been called (e.g. during shell completion), the settings file is loaded
+/// on first use, with any errors ignored in favour of the defaults.
+pub fn settings() -> &'static Settings {
+    SETTINGS.get_or_init(|| Settings::load().unwrap_or_default())
+}
+
+/// Create a new command for the configured Podman binary.
+pub fn podman() -> Command {
+    Command::new(&settings().podman)
+}
+
+/// Create a new command for the configured Buildah binary.
+pub fn buildah() -> Command {
+    Command::new(&settings().buildah)
+}
+
+/// Determines the path of the settings file.
+///
+/// - `$XDG_CONFIG_HOME/box/config.toml`
+/// - `$HOME/.config/box/config.toml`
+pub fn settings_path() -> Result<PathBuf> {
+    Ok(
+        crate::build::config_home()
+            .context("Could not find a valid path for the settings file")?
+            .join("box")
+            .join("config.toml")
+    )
+}
@@ -14,9 +14,29 @@ end
 
 trap bx
 
+set -q __BOX_BUILDAH; or set -gx __BOX_BUILDAH buildah
+
 function buildah
     if [ $argv[1] = 'from' ]
-        set -l ctr (command buildah $argv)
+        # Recorded so the working container can be recreated from a build checkpoint.
+        set -gx __BOX_BUILD_FROM (string join \x1F -- $argv[2..-1])
+
+        # Every working container is named (by Box, unless the definition does so itself) and recorded
+        # before it is created, so Box can always clean them up.
+        set -l name (string replace -rf -- '^--name=' '' $argv[2..-1])
+
+        if set -l i (contains -i -- --name $argv)
+            set name $argv[(math $i + 1)]
+        end
+
+        if [ -z "$name" ]
+            set name $__BOX_BUILD_WORKING-(count < $__BOX_BUILD_CONTAINERS)
+            set argv from --name $name $argv[2..-1]
+        end
+
+        echo $name[-1] >> $__BOX_BUILD_CONTAINERS
+
+        set -l ctr (command $__BOX_BUILDAH $argv)
 
         if [ $status -ne 0 ]
             exit $status
@@ -24,19 +44,27 @@ function buildah
         
         buildah config \
             -a manager=box \
+            -a box.checkpoint= \
             -a box.path=$__BOX_BUILD_PATH \
             -a box.hash=$__BOX_BUILD_HASH \
             -a box.tree=$__BOX_BUILD_TREE \
             -a box.name=$__BOX_BUILD_NAME \
+            -a "box.depends_on=$__BOX_BUILD_DEPENDS" \
+            -a "box.group=$__BOX_BUILD_GROUP" \
             $ctr
 
         buildah config -a \
             box.instantiate=$__BOX_BUILD_INSTANTIATE \
             $ctr
 
+        # Only override an inherited pod if this definition sets one.
+        if [ -n "$__BOX_BUILD_POD" ]
+            buildah config -a "box.pod=$__BOX_BUILD_POD" $ctr
+        end
+
         set -gx __BOX_BUILD_CTR $ctr
     else
-        command buildah $argv
+        command $__BOX_BUILDAH $argv
 
         if [ $status -ne 0 ]
             exit $status
@@ -2,23 +2,53 @@ set -eu
 
 buildah() {
     if [ "$1" = 'from' ]; then
-        ctr=$(command buildah "$@")
+        # Recorded so the working container can be recreated from a build checkpoint.
+        export __BOX_BUILD_FROM="$(shift; printf '%s\037' "$@")"
+
+        # Every working container is named (by Box, unless the definition does so itself) and recorded
+        # before it is created, so Box can always clean them up.
+        __box_name=""
+        __box_prev=""
+
+        for __box_arg do
+            case "$__box_prev" in --name) __box_name="$__box_arg" ;; esac
+            case "$__box_arg" in --name=*) __box_name="${__box_arg#--name=}" ;; esac
+            __box_prev="$__box_arg"
+        done
+
+        if [ -z "$__box_name" ]; then
+            __box_name="$__BOX_BUILD_WORKING-$(wc -l < "$__BOX_BUILD_CONTAINERS" | tr -d ' ')"
+            shift
+            set -- from --name "$__box_name" "$@"
+        fi
+
+        echo "$__box_name" >> "$__BOX_BUILD_CONTAINERS"
+
+        ctr=$(command "${__BOX_BUILDAH:-buildah}" "$@")
         
         buildah config \
             -a manager=box \
+            -a box.checkpoint= \
             -a box.path=$__BOX_BUILD_PATH \
             -a box.hash=$__BOX_BUILD_HASH \
             -a box.tree=$__BOX_BUILD_TREE \
             -a box.name=$__BOX_BUILD_NAME \
+            -a "box.depends_on=$__BOX_BUILD_DEPENDS" \
+            -a "box.group=$__BOX_BUILD_GROUP" \
             "$ctr"
 
         buildah config -a \
             box.instantiate=$__BOX_BUILD_INSTANTIATE \
             "$ctr"
 
+        # Only override an inherited pod if this definition sets one.
+        if [ -n "$__BOX_BUILD_POD" ]; then
+            buildah config -a "box.pod=$__BOX_BUILD_POD" "$ctr"
+        fi
+
         export __BOX_BUILD_CTR="$ctr"
     else
-        command buildah "$@"
+        command "${__BOX_BUILDAH:-buildah}" "$@"
     fi
 }
 